@@ -0,0 +1,85 @@
+/// Base32 alphabet without the characters that are easy to confuse (0/O, 1/I).
+/// It only has one case, so encoded names work on case insensitive file systems.
+const ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Encodes bytes in base32, the last character is padded with zero bits
+pub fn encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(5) * 8);
+
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            text.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        text.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    text
+}
+
+/// Decodes base32 text in any case, returns the first invalid character on error.
+/// The bits that don't fill a byte are ignored.
+pub fn decode(text: &str) -> Result<Vec<u8>, char> {
+    let mut bytes = Vec::with_capacity(text.len() * 5 / 8);
+
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in text.chars() {
+        let value = ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())
+            .ok_or(c)?;
+
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for len in 0..=40usize {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 37 + 11) as u8).collect();
+            let text = encode(&bytes);
+
+            assert_eq!(text.len(), (len * 8).div_ceil(5));
+            assert_eq!(decode(&text).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn known_values() {
+        assert_eq!(encode(&[]), "");
+        assert_eq!(encode(&[0]), "AA");
+        assert_eq!(encode(&[0xff]), "96");
+        assert_eq!(encode(&[0; 5]), "AAAAAAAA");
+    }
+
+    #[test]
+    fn decode_ignores_case() {
+        let text = encode(b"Private notes");
+        assert_eq!(decode(&text.to_lowercase()).unwrap(), b"Private notes");
+    }
+
+    #[test]
+    fn invalid_characters_are_rejected() {
+        for c in ['0', '1', 'O', 'I', '=', '/', '.'] {
+            assert_eq!(decode(&format!("AB{}CD", c)), Err(c));
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use std::io::{Read, Write};
+
+/// Compresses the content with deflate
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .map_err(|e| format!("Compression failed: {}", e))?;
+    encoder
+        .finish()
+        .map_err(|e| format!("Compression failed: {}", e))
+}
+
+/// Decompresses content compressed by `compress`
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut content = Vec::new();
+    DeflateDecoder::new(data)
+        .read_to_end(&mut content)
+        .map_err(|e| format!("Decompression failed: {}", e))?;
+    Ok(content)
+}
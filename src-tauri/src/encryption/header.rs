@@ -0,0 +1,378 @@
+use crate::encryption::identity::IDENTITY_TAG_LEN;
+
+/// Magic bytes at the start of every versioned .lockd file
+pub const MAGIC: [u8; 4] = *b"LCKD";
+
+/// Current version of the file format.
+/// Since version 2 the header is authenticated as associated data of the cipher.
+pub const FORMAT_VERSION: u8 = 2;
+
+/// The header ends with the identity tag of the file
+pub const FLAG_IDENTITY: u8 = 0x01;
+
+/// The body is encrypted in chunks (nonce prefix + chunks) instead of a single message
+pub const FLAG_STREAM: u8 = 0x02;
+
+/// The content was padded before encryption to hide its length
+pub const FLAG_PADDED: u8 = 0x04;
+
+/// The content was compressed with deflate before encryption
+pub const FLAG_COMPRESSED: u8 = 0x08;
+
+/// Flags understood by this version of the format, the rest must be zero
+const KNOWN_FLAGS: u8 = FLAG_IDENTITY | FLAG_STREAM | FLAG_PADDED | FLAG_COMPRESSED;
+
+/// Length of the fixed part of the header (everything before the salt)
+const FIXED_LEN: usize = 21;
+
+/// Longest possible header, with the longest salt and an identity tag
+pub const MAX_HEADER_LEN: usize = FIXED_LEN + u8::MAX as usize + IDENTITY_TAG_LEN;
+
+/// Ciphers that can be used to encrypt the body of a file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Cipher {
+    /// AES-256-GCM with random 96-bit nonces
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm = 1,
+    /// XChaCha20-Poly1305 with random 192-bit nonces, which can't collide
+    /// however many files are written with the same key
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305 = 2,
+}
+
+impl Cipher {
+    pub fn from_id(id: u8) -> Result<Self, String> {
+        match id {
+            1 => Ok(Cipher::Aes256Gcm),
+            2 => Ok(Cipher::XChaCha20Poly1305),
+            _ => Err(format!("Unsupported cipher (id {})", id)),
+        }
+    }
+
+    /// Length of the nonce written before the ciphertext
+    pub fn nonce_len(self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => 12,
+            Cipher::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+/// Key derivation functions used to get the key of a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kdf {
+    /// Argon2id of the password with the zero padded username as salt.
+    /// The username is never written to the file, so the salt is empty.
+    Argon2idUsername = 1,
+    /// Argon2id of the login secret gives a master key, then HKDF-SHA256 gives the file key.
+    /// The salt is the random master salt followed by the random file salt.
+    Argon2idHkdf = 2,
+    /// HKDF-SHA256 of the data key of a vault, which is stored in the vault keyfile.
+    /// The salt is the id of the vault followed by the random file salt,
+    /// the Argon2 parameters are not used.
+    VaultHkdf = 3,
+}
+
+impl Kdf {
+    pub fn from_id(id: u8) -> Result<Self, String> {
+        match id {
+            1 => Ok(Kdf::Argon2idUsername),
+            2 => Ok(Kdf::Argon2idHkdf),
+            3 => Ok(Kdf::VaultHkdf),
+            _ => Err(format!("Unsupported key derivation function (id {})", id)),
+        }
+    }
+}
+
+/// Argon2 cost parameters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// Written when the key derivation doesn't use Argon2
+    pub const UNUSED: KdfParams = KdfParams {
+        memory: 0,
+        iterations: 0,
+        parallelism: 0,
+    };
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Header written before the nonce and the ciphertext of a file.
+///
+/// Layout (integers are little endian):
+/// magic (4) | version (1) | cipher (1) | kdf (1) | flags (1) |
+/// memory (4) | iterations (4) | parallelism (4) | salt length (1) | salt |
+/// identity tag (16, if FLAG_IDENTITY is set)
+///
+/// The body is nonce (12 or 24, depending on the cipher) | ciphertext,
+/// or nonce prefix (7 or 19) | chunks if FLAG_STREAM is set.
+/// If FLAG_PADDED is set the decrypted content ends with 0x80 and zeros, which are removed,
+/// then it is decompressed if FLAG_COMPRESSED is set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileHeader {
+    pub version: u8,
+    pub cipher: Cipher,
+    pub kdf: Kdf,
+    pub flags: u8,
+    pub kdf_params: KdfParams,
+    pub salt: Vec<u8>,
+    /// Tag of the location of the file inside its vault
+    pub identity: Option<[u8; IDENTITY_TAG_LEN]>,
+}
+
+impl FileHeader {
+    /// Creates a header for the current version of the format
+    pub fn new(
+        cipher: Cipher,
+        kdf: Kdf,
+        kdf_params: KdfParams,
+        salt: Vec<u8>,
+        identity: Option<[u8; IDENTITY_TAG_LEN]>,
+    ) -> Self {
+        FileHeader {
+            version: FORMAT_VERSION,
+            cipher,
+            kdf,
+            flags: if identity.is_some() { FLAG_IDENTITY } else { 0 },
+            kdf_params,
+            salt,
+            identity,
+        }
+    }
+
+    /// Marks the body as encrypted in chunks
+    pub fn streamed(mut self) -> Self {
+        self.flags |= FLAG_STREAM;
+        self
+    }
+
+    pub fn is_streamed(&self) -> bool {
+        self.flags & FLAG_STREAM != 0
+    }
+
+    /// Marks the content as padded
+    pub fn padded(mut self) -> Self {
+        self.flags |= FLAG_PADDED;
+        self
+    }
+
+    pub fn is_padded(&self) -> bool {
+        self.flags & FLAG_PADDED != 0
+    }
+
+    /// Marks the content as compressed
+    pub fn compressed(mut self) -> Self {
+        self.flags |= FLAG_COMPRESSED;
+        self
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+
+    /// Marks the content as stored like the one of another header: in chunks, padded, compressed
+    pub fn with_content_flags(mut self, other: &FileHeader) -> Self {
+        self.flags |= other.flags & (FLAG_STREAM | FLAG_PADDED | FLAG_COMPRESSED);
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FIXED_LEN + self.salt.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(self.version);
+        bytes.push(self.cipher as u8);
+        bytes.push(self.kdf as u8);
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.kdf_params.memory.to_le_bytes());
+        bytes.extend_from_slice(&self.kdf_params.iterations.to_le_bytes());
+        bytes.extend_from_slice(&self.kdf_params.parallelism.to_le_bytes());
+        bytes.push(self.salt.len() as u8);
+        bytes.extend_from_slice(&self.salt);
+        if let Some(identity) = &self.identity {
+            bytes.extend_from_slice(identity);
+        }
+        bytes
+    }
+
+    /// Parses the header at the start of the file data.
+    /// Returns the header and its length, or None if the data has no magic bytes (legacy file).
+    pub fn parse(data: &[u8]) -> Result<Option<(FileHeader, usize)>, String> {
+        if !data.starts_with(&MAGIC) {
+            return Ok(None);
+        }
+
+        if data.len() < FIXED_LEN {
+            return Err("File header is truncated".to_string());
+        }
+
+        let version = data[4];
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported file format version {}, try updating the app",
+                version
+            ));
+        }
+
+        let cipher = Cipher::from_id(data[5])?;
+        let kdf = Kdf::from_id(data[6])?;
+
+        let flags = data[7];
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(format!("Unsupported file flags: {:#04x}", flags));
+        }
+
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        let kdf_params = KdfParams {
+            memory: read_u32(8),
+            iterations: read_u32(12),
+            parallelism: read_u32(16),
+        };
+
+        let salt_len = data[20] as usize;
+        let salt_end = FIXED_LEN + salt_len;
+        let header_len = match flags & FLAG_IDENTITY {
+            0 => salt_end,
+            _ => salt_end + IDENTITY_TAG_LEN,
+        };
+        if data.len() < header_len {
+            return Err("File header is truncated".to_string());
+        }
+        let salt = data[FIXED_LEN..salt_end].to_vec();
+        let identity = match flags & FLAG_IDENTITY {
+            0 => None,
+            _ => data[salt_end..header_len].try_into().ok(),
+        };
+
+        Ok(Some((
+            FileHeader {
+                version,
+                cipher,
+                kdf,
+                flags,
+                kdf_params,
+                salt,
+                identity,
+            },
+            header_len,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_header() -> FileHeader {
+        FileHeader::new(
+            Cipher::XChaCha20Poly1305,
+            Kdf::Argon2idHkdf,
+            KdfParams::default(),
+            vec![7; 32],
+            Some([9; IDENTITY_TAG_LEN]),
+        )
+        .streamed()
+        .padded()
+        .compressed()
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let header = sample_header();
+        let mut data = header.to_bytes();
+        let header_len = data.len();
+        data.extend_from_slice(b"body");
+
+        let (parsed, parsed_len) = FileHeader::parse(&data).unwrap().unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(parsed_len, header_len);
+        assert!(parsed.is_streamed() && parsed.is_padded() && parsed.is_compressed());
+    }
+
+    #[test]
+    fn parse_without_identity() {
+        let header = FileHeader::new(
+            Cipher::Aes256Gcm,
+            Kdf::VaultHkdf,
+            KdfParams::UNUSED,
+            vec![1; 48],
+            None,
+        );
+        let data = header.to_bytes();
+
+        let (parsed, parsed_len) = FileHeader::parse(&data).unwrap().unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(parsed_len, FIXED_LEN + 48);
+    }
+
+    #[test]
+    fn legacy_file_has_no_header() {
+        assert_eq!(FileHeader::parse(b"legacy ciphertext").unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_header_is_rejected() {
+        let data = sample_header().to_bytes();
+        for len in [MAGIC.len(), FIXED_LEN - 1, data.len() - 1] {
+            assert!(FileHeader::parse(&data[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn unknown_values_are_rejected() {
+        let data = sample_header().to_bytes();
+
+        let mut newer = data.clone();
+        newer[4] = FORMAT_VERSION + 1;
+        assert!(FileHeader::parse(&newer).is_err());
+
+        let mut cipher = data.clone();
+        cipher[5] = 0xff;
+        assert!(FileHeader::parse(&cipher).is_err());
+
+        let mut kdf = data.clone();
+        kdf[6] = 0xff;
+        assert!(FileHeader::parse(&kdf).is_err());
+
+        let mut flags = data;
+        flags[7] |= 0x80;
+        assert!(FileHeader::parse(&flags).is_err());
+    }
+
+    #[test]
+    fn content_flags_are_copied() {
+        let header = FileHeader::new(
+            Cipher::Aes256Gcm,
+            Kdf::VaultHkdf,
+            KdfParams::UNUSED,
+            vec![0; 48],
+            None,
+        )
+        .with_content_flags(&sample_header());
+
+        assert!(header.is_streamed() && header.is_padded() && header.is_compressed());
+        assert_eq!(header.flags & FLAG_IDENTITY, 0);
+    }
+}
@@ -0,0 +1,42 @@
+use crate::encryption::secret::SecretKey;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Context used to derive the identity key from the data key of a vault
+const IDENTITY_KEY_INFO: &[u8] = b"privatenotes file identity v1";
+
+/// Length of the identity tag stored in the header of a file
+pub const IDENTITY_TAG_LEN: usize = 16;
+
+/// Key that tags the files of a vault with their location, so a file copied over
+/// another one or moved outside of the app is detected when it is decrypted.
+/// The tag only binds a file to its path, not to a version: an older copy of the same file
+/// put back at the same path is still accepted. Protecting against that would need a counter
+/// stored outside of the vault, so replaying old versions is left out of scope.
+#[derive(Clone)]
+pub struct IdentityKey(SecretKey);
+
+impl IdentityKey {
+    /// Derives the identity key from the data key of a vault
+    pub fn derive(data_key: &[u8; 32]) -> Result<Self, String> {
+        let mut key = SecretKey::zeroed();
+        Hkdf::<Sha256>::new(None, data_key)
+            .expand(IDENTITY_KEY_INFO, &mut key[..])
+            .map_err(|e| format!("Failed to derive identity key: {}", e))?;
+
+        Ok(IdentityKey(key))
+    }
+
+    /// Tag of a file from its path relative to the vault, with / as separator.
+    /// It doesn't depend on the key of the file, so re-encrypting a file keeps it.
+    pub fn tag(&self, relative_path: &str) -> Result<[u8; IDENTITY_TAG_LEN], String> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0[..])
+            .map_err(|e| format!("Failed to create identity tag: {}", e))?;
+        mac.update(relative_path.as_bytes());
+
+        let mut tag = [0u8; IDENTITY_TAG_LEN];
+        tag.copy_from_slice(&mac.finalize().into_bytes()[..IDENTITY_TAG_LEN]);
+        Ok(tag)
+    }
+}
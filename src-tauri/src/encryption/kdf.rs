@@ -0,0 +1,149 @@
+use crate::encryption::header::KdfParams;
+use crate::encryption::secret::SecretKey;
+use argon2::{Algorithm, Argon2, Params, Version};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// Length of the random salts stored in the file headers
+pub const SALT_LEN: usize = 16;
+
+/// Context used to derive the key of each file from a master key
+const FILE_KEY_INFO: &[u8] = b"privatenotes file key v1";
+
+/// Limits for the Argon2 parameters, also applied to the ones read from files
+/// so a crafted header can't make the app allocate huge amounts of memory
+const MIN_MEMORY: u32 = 8 * 1024;
+const MAX_MEMORY: u32 = 4 * 1024 * 1024;
+const MAX_ITERATIONS: u32 = 64;
+const MAX_PARALLELISM: u32 = 16;
+
+/// Highest memory cost the calibration will pick (1 GiB)
+const MAX_CALIBRATED_MEMORY: u32 = 1024 * 1024;
+
+/// Builds the secret the master keys are derived from.
+/// The username is length prefixed so "ab" + "c" and "a" + "bc" give different secrets.
+pub fn login_secret(username: &str, password: &str) -> Zeroizing<Vec<u8>> {
+    let mut secret = Zeroizing::new(Vec::with_capacity(4 + username.len() + password.len()));
+    secret.extend_from_slice(&(username.len() as u32).to_le_bytes());
+    secret.extend_from_slice(username.as_bytes());
+    secret.extend_from_slice(password.as_bytes());
+    secret
+}
+
+/// Derives the key used by older versions of the app,
+/// Argon2id with the default parameters and the username as salt
+pub fn derive_legacy_key(username: &str, password: &str) -> Result<SecretKey, String> {
+    // Use the username as the salt for Argon2, min length is 16 bytes
+    let mut username_bytes = username.as_bytes().to_vec();
+    if username_bytes.len() < 16 {
+        username_bytes.resize(16, 0);
+    }
+
+    let mut key = SecretKey::zeroed();
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), username_bytes.as_slice(), &mut key[..])
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+
+    Ok(key)
+}
+
+/// Checks that the Argon2 parameters are within the supported limits
+pub fn check_params(params: &KdfParams) -> Result<(), String> {
+    if !(MIN_MEMORY..=MAX_MEMORY).contains(&params.memory) {
+        return Err(format!(
+            "Memory cost must be between {} and {} KiB",
+            MIN_MEMORY, MAX_MEMORY
+        ));
+    }
+    if !(1..=MAX_ITERATIONS).contains(&params.iterations) {
+        return Err(format!(
+            "Iterations must be between 1 and {}",
+            MAX_ITERATIONS
+        ));
+    }
+    if !(1..=MAX_PARALLELISM).contains(&params.parallelism) {
+        return Err(format!(
+            "Parallelism must be between 1 and {}",
+            MAX_PARALLELISM
+        ));
+    }
+    Ok(())
+}
+
+/// Derives a master key from the login secret with Argon2id
+pub fn derive_master_key(
+    secret: &[u8],
+    salt: &[u8; SALT_LEN],
+    params: &KdfParams,
+) -> Result<SecretKey, String> {
+    check_params(params)?;
+
+    let argon_params = Params::new(
+        params.memory,
+        params.iterations,
+        params.parallelism,
+        Some(32),
+    )
+    .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+
+    let mut key = SecretKey::zeroed();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, argon_params)
+        .hash_password_into(secret, salt, &mut key[..])
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+
+    Ok(key)
+}
+
+/// Derives the key of a single file from a master key and the salt of the file
+pub fn derive_file_key(master_key: &[u8; 32], file_salt: &[u8]) -> Result<SecretKey, String> {
+    let mut key = SecretKey::zeroed();
+    Hkdf::<Sha256>::new(Some(file_salt), master_key)
+        .expand(FILE_KEY_INFO, &mut key[..])
+        .map_err(|e| format!("Failed to derive file key: {}", e))?;
+
+    Ok(key)
+}
+
+/// Measures how long it takes to derive a key with the given parameters
+fn measure(params: &KdfParams) -> Result<Duration, String> {
+    let secret: [u8; 32] = rand::random();
+    let salt: [u8; SALT_LEN] = rand::random();
+
+    let start = Instant::now();
+    derive_master_key(&secret, &salt, params)?;
+    Ok(start.elapsed())
+}
+
+/// Finds the Argon2 parameters that take about the target time on this machine.
+/// Memory is raised first because it is what makes attacks with GPUs expensive,
+/// then the iterations fill the remaining time.
+pub fn calibrate(target: Duration) -> Result<KdfParams, String> {
+    let parallelism = std::thread::available_parallelism()
+        .map(|n| n.get() as u32)
+        .unwrap_or(1)
+        .min(4);
+
+    let mut params = KdfParams {
+        parallelism,
+        ..KdfParams::default()
+    };
+
+    // The time grows linearly with the memory and the iterations
+    let elapsed = measure(&params)?.as_secs_f64().max(0.001);
+    let ratio = target.as_secs_f64() / elapsed;
+
+    let memory =
+        (params.memory as f64 * ratio).clamp(MIN_MEMORY as f64, MAX_CALIBRATED_MEMORY as f64);
+    let iterations = params.iterations as f64 * ratio * params.memory as f64 / memory;
+    params.memory = memory as u32;
+    params.iterations = (iterations.round() as u32).clamp(1, MAX_ITERATIONS);
+
+    // Measure again to correct the estimation, bigger memory sizes are slower per KiB
+    let elapsed = measure(&params)?.as_secs_f64().max(0.001);
+    let iterations = params.iterations as f64 * target.as_secs_f64() / elapsed;
+    params.iterations = (iterations.round() as u32).clamp(1, MAX_ITERATIONS);
+
+    Ok(params)
+}
@@ -0,0 +1,336 @@
+use crate::encryption::header::{Cipher, KdfParams};
+use crate::encryption::kdf::SALT_LEN;
+use crate::encryption::secret::SecretKey;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use zeroize::Zeroizing;
+
+/// Length of the random identifier of a vault
+pub const VAULT_ID_LEN: usize = 16;
+
+/// Current version of the keyfile format
+const KEYFILE_VERSION: u8 = 1;
+
+/// Prefix of the associated data used to wrap the data key
+const WRAP_AAD: &[u8] = b"privatenotes vault key v1";
+
+/// Prefix of the associated data of the verifier
+const VERIFIER_AAD: &[u8] = b"privatenotes vault verifier v1";
+
+/// Known content of the verifier
+const VERIFIER_CONTENT: &[u8] = b"privatenotes password check";
+
+/// Error of a key derived from another username or password than the one of the vault
+pub const WRONG_PASSWORD_ERROR: &str = "Wrong username or password for this vault";
+
+/// Keyfile stored in the .lockd folder of a vault.
+/// It holds the random data key of the vault wrapped by a key derived from the password,
+/// so changing the password only needs to rewrap this key.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct VaultKeyfile {
+    pub version: u8,
+    pub id: String,
+    pub kdf_params: KdfParams,
+    pub salt: String,
+    pub wrapped_key: String,
+    /// Data key wrapped by the key of the recovery code, if the vault has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_key: Option<String>,
+    /// The names of the notes and folders are encrypted on disk
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypt_names: bool,
+    /// Cipher of the new files of the vault, the existing files keep theirs
+    #[serde(default)]
+    pub cipher: Cipher,
+    /// Known content encrypted with the key derived from the password,
+    /// it tells a wrong password apart from a damaged wrapped key.
+    /// The keyfiles written by older versions get it when they are unlocked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verifier: Option<String>,
+    /// Every file of the vault is encrypted with the data key, none needs the password anymore
+    #[serde(default)]
+    pub migrated: bool,
+}
+
+impl VaultKeyfile {
+    /// Wraps the data key of a vault with the key derived from the password
+    pub fn new(
+        id: &[u8; VAULT_ID_LEN],
+        salt: &[u8; SALT_LEN],
+        kdf_params: KdfParams,
+        wrapping_key: &[u8; 32],
+        data_key: &[u8; 32],
+    ) -> Result<Self, String> {
+        Ok(VaultKeyfile {
+            version: KEYFILE_VERSION,
+            id: hex::encode(id),
+            kdf_params,
+            salt: hex::encode(salt),
+            wrapped_key: hex::encode(wrap_key(id, wrapping_key, data_key)?),
+            recovery_key: None,
+            encrypt_names: false,
+            cipher: Cipher::default(),
+            verifier: Some(hex::encode(seal_verifier(id, wrapping_key)?)),
+            migrated: false,
+        })
+    }
+
+    /// Wraps the data key again with the key derived from a new password, keeps the recovery key
+    pub fn rewrap(
+        &mut self,
+        salt: &[u8; SALT_LEN],
+        kdf_params: KdfParams,
+        wrapping_key: &[u8; 32],
+        data_key: &[u8; 32],
+    ) -> Result<(), String> {
+        self.wrapped_key = hex::encode(wrap_key(&self.id()?, wrapping_key, data_key)?);
+        self.salt = hex::encode(salt);
+        self.kdf_params = kdf_params;
+        self.set_verifier(wrapping_key)
+    }
+
+    /// Writes the verifier of the key derived from the password
+    fn set_verifier(&mut self, wrapping_key: &[u8; 32]) -> Result<(), String> {
+        self.verifier = Some(hex::encode(seal_verifier(&self.id()?, wrapping_key)?));
+        Ok(())
+    }
+
+    /// Checks the key derived from the password against the verifier.
+    /// None if the keyfile has no verifier.
+    pub fn verify(&self, wrapping_key: &[u8; 32]) -> Result<Option<bool>, String> {
+        let Some(verifier) = &self.verifier else {
+            return Ok(None);
+        };
+        let verifier = hex::decode(verifier).map_err(|_| "Invalid verifier in vault keyfile")?;
+        let content = open(wrapping_key, &verifier, &verifier_aad(&self.id()?))?;
+
+        Ok(Some(content.as_deref() == Some(VERIFIER_CONTENT)))
+    }
+
+    /// Wraps the data key with the key derived from a recovery code, replacing the previous one
+    pub fn set_recovery_key(
+        &mut self,
+        recovery_key: &[u8; 32],
+        data_key: &[u8; 32],
+    ) -> Result<(), String> {
+        self.recovery_key = Some(hex::encode(wrap_key(&self.id()?, recovery_key, data_key)?));
+        Ok(())
+    }
+
+    /// Parses the keyfile from its JSON content
+    pub fn from_json(content: &str) -> Result<Self, String> {
+        let keyfile: VaultKeyfile =
+            serde_json::from_str(content).map_err(|e| format!("Invalid vault keyfile: {}", e))?;
+
+        if keyfile.version == 0 || keyfile.version > KEYFILE_VERSION {
+            return Err(format!(
+                "Unsupported vault keyfile version {}, try updating the app",
+                keyfile.version
+            ));
+        }
+
+        Ok(keyfile)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize vault keyfile: {}", e))
+    }
+
+    pub fn id(&self) -> Result<[u8; VAULT_ID_LEN], String> {
+        decode_array(&self.id).ok_or("Invalid id in vault keyfile".to_string())
+    }
+
+    pub fn salt(&self) -> Result<[u8; SALT_LEN], String> {
+        decode_array(&self.salt).ok_or("Invalid salt in vault keyfile".to_string())
+    }
+
+    /// Unwraps the data key of the vault with the key derived from the password.
+    /// If it fails, the verifier tells if the password is wrong or the keyfile is damaged.
+    pub fn unwrap_key(&self, wrapping_key: &[u8; 32]) -> Result<SecretKey, String> {
+        let wrapped_key =
+            hex::decode(&self.wrapped_key).map_err(|_| "Invalid key in vault keyfile")?;
+        unwrap_key(&self.id()?, wrapping_key, &wrapped_key).map_err(|err| {
+            match (err.as_str(), self.verify(wrapping_key)) {
+                (WRONG_PASSWORD_ERROR, Ok(Some(true))) => {
+                    "The key in the vault keyfile is damaged, \
+                     the vault can still be opened with its recovery code"
+                        .to_string()
+                }
+                _ => err,
+            }
+        })
+    }
+
+    /// Unwraps the data key of the vault with the key derived from the recovery code
+    pub fn unwrap_recovery_key(&self, recovery_key: &[u8; 32]) -> Result<SecretKey, String> {
+        let wrapped_key = self
+            .recovery_key
+            .as_ref()
+            .ok_or("The vault has no recovery code")?;
+        let wrapped_key =
+            hex::decode(wrapped_key).map_err(|_| "Invalid recovery key in vault keyfile")?;
+        unwrap_key(&self.id()?, recovery_key, &wrapped_key)
+            .map_err(|_| "Wrong recovery code for this vault".to_string())
+    }
+}
+
+/// Decodes a hex string into a fixed size array
+fn decode_array<const N: usize>(value: &str) -> Option<[u8; N]> {
+    hex::decode(value).ok()?.try_into().ok()
+}
+
+/// Associated data of the wrapped key, binds it to the vault
+fn wrap_aad(id: &[u8; VAULT_ID_LEN]) -> Vec<u8> {
+    [WRAP_AAD, id.as_slice()].concat()
+}
+
+/// Associated data of the verifier, binds it to the vault
+fn verifier_aad(id: &[u8; VAULT_ID_LEN]) -> Vec<u8> {
+    [VERIFIER_AAD, id.as_slice()].concat()
+}
+
+/// Encrypts a message and returns (nonce, encrypted message)
+fn seal(key: &[u8; 32], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher =
+        Aes256Gcm::new_from_slice(key).map_err(|e| format!("Failed to create cipher: {}", e))?;
+
+    let nonce_bytes: [u8; 12] = rand::random();
+    let encrypted = cipher
+        .encrypt(Nonce::from_slice(&nonce_bytes), Payload { msg, aad })
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+
+    Ok([nonce_bytes.as_slice(), &encrypted].concat())
+}
+
+/// Decrypts a message from (nonce, encrypted message), None if the key doesn't match
+fn open(key: &[u8; 32], data: &[u8], aad: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    if data.len() < 12 {
+        return Err("Invalid key in vault keyfile".to_string());
+    }
+
+    let cipher =
+        Aes256Gcm::new_from_slice(key).map_err(|e| format!("Failed to create cipher: {}", e))?;
+
+    Ok(cipher
+        .decrypt(
+            Nonce::from_slice(&data[..12]),
+            Payload {
+                msg: &data[12..],
+                aad,
+            },
+        )
+        .ok())
+}
+
+/// Encrypts the known content of the verifier with the key derived from the password
+fn seal_verifier(id: &[u8; VAULT_ID_LEN], wrapping_key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    seal(wrapping_key, VERIFIER_CONTENT, &verifier_aad(id))
+}
+
+/// Encrypts a data key and returns (nonce, encrypted key)
+pub fn wrap_key(
+    id: &[u8; VAULT_ID_LEN],
+    wrapping_key: &[u8; 32],
+    data_key: &[u8; 32],
+) -> Result<Vec<u8>, String> {
+    seal(wrapping_key, data_key, &wrap_aad(id))
+        .map_err(|e| format!("Failed to wrap vault key: {}", e))
+}
+
+/// Decrypts a data key from (nonce, encrypted key)
+pub fn unwrap_key(
+    id: &[u8; VAULT_ID_LEN],
+    wrapping_key: &[u8; 32],
+    wrapped_key: &[u8],
+) -> Result<SecretKey, String> {
+    let data_key = open(wrapping_key, wrapped_key, &wrap_aad(id))?.ok_or(WRONG_PASSWORD_ERROR)?;
+
+    // Wipe the decrypted copy once it is in the key
+    SecretKey::from_slice(&Zeroizing::new(data_key))
+        .map_err(|_| "Invalid key in vault keyfile".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: [u8; VAULT_ID_LEN] = [1; VAULT_ID_LEN];
+    const SALT: [u8; SALT_LEN] = [2; SALT_LEN];
+    const WRAPPING_KEY: [u8; 32] = [3; 32];
+    const DATA_KEY: [u8; 32] = [4; 32];
+
+    fn keyfile() -> VaultKeyfile {
+        VaultKeyfile::new(&ID, &SALT, KdfParams::default(), &WRAPPING_KEY, &DATA_KEY).unwrap()
+    }
+
+    #[test]
+    fn wrap_round_trip() {
+        let keyfile = keyfile();
+        assert_eq!(*keyfile.unwrap_key(&WRAPPING_KEY).unwrap(), DATA_KEY);
+        assert_eq!(keyfile.verify(&WRAPPING_KEY).unwrap(), Some(true));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut keyfile = keyfile();
+        keyfile.set_recovery_key(&[5; 32], &DATA_KEY).unwrap();
+
+        let parsed = VaultKeyfile::from_json(&keyfile.to_json().unwrap()).unwrap();
+        assert_eq!(parsed.id().unwrap(), ID);
+        assert_eq!(parsed.salt().unwrap(), SALT);
+        assert_eq!(parsed.kdf_params, KdfParams::default());
+        assert_eq!(*parsed.unwrap_key(&WRAPPING_KEY).unwrap(), DATA_KEY);
+        assert_eq!(*parsed.unwrap_recovery_key(&[5; 32]).unwrap(), DATA_KEY);
+    }
+
+    #[test]
+    fn wrong_password_is_reported() {
+        let keyfile = keyfile();
+        assert_eq!(
+            keyfile.unwrap_key(&[9; 32]).unwrap_err(),
+            WRONG_PASSWORD_ERROR
+        );
+        assert_eq!(keyfile.verify(&[9; 32]).unwrap(), Some(false));
+        assert!(keyfile.unwrap_recovery_key(&[9; 32]).is_err());
+    }
+
+    #[test]
+    fn damaged_key_is_told_apart_from_wrong_password() {
+        let mut keyfile = keyfile();
+        let mut wrapped_key = hex::decode(&keyfile.wrapped_key).unwrap();
+        wrapped_key[20] ^= 1;
+        keyfile.wrapped_key = hex::encode(wrapped_key);
+
+        let err = keyfile.unwrap_key(&WRAPPING_KEY).unwrap_err();
+        assert_ne!(err, WRONG_PASSWORD_ERROR);
+        assert!(err.contains("damaged"));
+    }
+
+    #[test]
+    fn wrapped_key_is_bound_to_the_vault() {
+        let wrapped_key = wrap_key(&ID, &WRAPPING_KEY, &DATA_KEY).unwrap();
+        assert!(unwrap_key(&[7; VAULT_ID_LEN], &WRAPPING_KEY, &wrapped_key).is_err());
+        assert!(unwrap_key(&ID, &WRAPPING_KEY, &wrapped_key[..11]).is_err());
+    }
+
+    #[test]
+    fn rewrap_changes_the_password() {
+        let mut keyfile = keyfile();
+        keyfile
+            .rewrap(&[6; SALT_LEN], KdfParams::default(), &[8; 32], &DATA_KEY)
+            .unwrap();
+
+        assert_eq!(*keyfile.unwrap_key(&[8; 32]).unwrap(), DATA_KEY);
+        assert!(keyfile.unwrap_key(&WRAPPING_KEY).is_err());
+        assert_eq!(keyfile.salt().unwrap(), [6; SALT_LEN]);
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let mut keyfile = keyfile();
+        keyfile.version = KEYFILE_VERSION + 1;
+        assert!(VaultKeyfile::from_json(&keyfile.to_json().unwrap()).is_err());
+        assert!(VaultKeyfile::from_json("{}").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::encryption::header::{Cipher, KdfParams};
+use crate::encryption::identity::{IdentityKey, IDENTITY_TAG_LEN};
+use crate::encryption::kdf::{derive_legacy_key, derive_master_key, login_secret, SALT_LEN};
+use crate::encryption::keyfile::VAULT_ID_LEN;
+use crate::encryption::names::NameKey;
+use crate::encryption::options::EncryptionOptions;
+use crate::encryption::secret::SecretKey;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Context of the tag that checks the password when the app is unlocked
+const SESSION_TAG_INFO: &[u8] = b"privatenotes session unlock v1";
+
+/// Error of a file whose key the session doesn't have, the frontend asks for the password
+/// and the key is derived with `derive_missing_keys`
+pub const PASSWORD_NEEDED_ERROR: &str =
+    "This file was encrypted with another key of your account, enter your password again to open it";
+
+/// Master keys by salt and parameters
+type MasterKeys = HashMap<([u8; SALT_LEN], KdfParams), SecretKey>;
+
+/// Keys that were needed by a file but that the session doesn't have
+#[derive(Default)]
+struct MissingKeys {
+    masters: HashSet<([u8; SALT_LEN], KdfParams)>,
+    legacy: bool,
+}
+
+/// Data key of an opened vault
+struct VaultKey {
+    id: [u8; VAULT_ID_LEN],
+    root: PathBuf,
+    key: SecretKey,
+    name_key: NameKey,
+    identity_key: IdentityKey,
+    /// New names are encrypted on disk
+    encrypt_names: bool,
+    /// Cipher of the new files
+    cipher: Cipher,
+}
+
+/// Keys of the logged in user.
+///
+/// The master key is derived once at login with the salt of the user on this device
+/// and is used for new files. Nothing derived from the password is kept besides the keys.
+/// Files written with another salt or by older versions need keys that aren't derived at login,
+/// they are derived when the user enters the password again and then cached.
+/// The data keys of the opened vaults are unwrapped from their keyfiles and kept here.
+/// Every key is wiped from memory when the keyring is dropped, at logout.
+pub struct Keyring {
+    master_salt: [u8; SALT_LEN],
+    master_params: KdfParams,
+    master_keys: Mutex<MasterKeys>,
+    /// Key of the files without a salt, derived the first time one is opened
+    legacy_key: Mutex<Option<SecretKey>>,
+    missing: Mutex<MissingKeys>,
+    vaults: Mutex<Vec<VaultKey>>,
+    /// How new files are encrypted, it can change while logged in
+    options: Mutex<EncryptionOptions>,
+}
+
+impl Keyring {
+    /// Derives the master key of a user with their salt, new files will use the given Argon2 parameters
+    pub fn new(
+        username: &str,
+        password: &str,
+        master_salt: [u8; SALT_LEN],
+        master_params: KdfParams,
+    ) -> Result<Self, String> {
+        let master_key = derive_master_key(
+            &login_secret(username, password),
+            &master_salt,
+            &master_params,
+        )?;
+
+        let mut master_keys = HashMap::new();
+        master_keys.insert((master_salt, master_params), master_key);
+
+        Ok(Keyring {
+            master_salt,
+            master_params,
+            master_keys: Mutex::new(master_keys),
+            legacy_key: Mutex::new(None),
+            missing: Mutex::new(MissingKeys::default()),
+            vaults: Mutex::new(Vec::new()),
+            options: Mutex::new(EncryptionOptions::default()),
+        })
+    }
+
+    /// Key of the files without a salt, derived with the username as salt.
+    /// It is only available once the password was entered again for a file that needs it.
+    pub fn legacy_key(&self) -> Result<SecretKey, String> {
+        match self.derived_legacy_key() {
+            Some(key) => Ok(key),
+            None => {
+                self.missing.lock().unwrap().legacy = true;
+                Err(PASSWORD_NEEDED_ERROR.to_string())
+            }
+        }
+    }
+
+    /// Key of the files without a salt if it was derived, without asking for it
+    pub fn derived_legacy_key(&self) -> Option<SecretKey> {
+        self.legacy_key.lock().unwrap().clone()
+    }
+
+    /// Returns the salt and parameters of the master key derived at login
+    pub fn master_derivation(&self) -> ([u8; SALT_LEN], KdfParams) {
+        (self.master_salt, self.master_params)
+    }
+
+    /// Returns the salt, parameters and key of the master key used for new files
+    pub fn current_master(&self) -> Result<([u8; SALT_LEN], KdfParams, SecretKey), String> {
+        let key = self.master_key(&self.master_salt, &self.master_params)?;
+        Ok((self.master_salt, self.master_params, key))
+    }
+
+    /// Returns the master key for the given salt and parameters.
+    /// Keys that weren't derived yet need the password again.
+    pub fn master_key(
+        &self,
+        salt: &[u8; SALT_LEN],
+        params: &KdfParams,
+    ) -> Result<SecretKey, String> {
+        if let Some(key) = self.master_keys.lock().unwrap().get(&(*salt, *params)) {
+            return Ok(key.clone());
+        }
+
+        self.missing
+            .lock()
+            .unwrap()
+            .masters
+            .insert((*salt, *params));
+        Err(PASSWORD_NEEDED_ERROR.to_string())
+    }
+
+    /// Derives the keys that files asked for, once the password is checked against the
+    /// master key of the session. The password is only used here and is not kept.
+    pub fn derive_missing_keys(&self, username: &str, password: &str) -> Result<(), String> {
+        let secret = login_secret(username, password);
+
+        let check = derive_master_key(&secret, &self.master_salt, &self.master_params)?;
+        if !verify_session_tag(&check, &self.session_tag()?) {
+            return Err("Wrong username or password".to_string());
+        }
+
+        // Derive without holding the locks, it can take a while
+        let missing = std::mem::take(&mut *self.missing.lock().unwrap());
+        for (salt, params) in missing.masters {
+            let key = derive_master_key(&secret, &salt, &params)?;
+            self.master_keys.lock().unwrap().insert((salt, params), key);
+        }
+        if missing.legacy {
+            *self.legacy_key.lock().unwrap() = Some(derive_legacy_key(username, password)?);
+        }
+
+        Ok(())
+    }
+
+    /// Tag of the login of this keyring, kept while the app is locked
+    /// so the password can be checked at unlock without keeping any key
+    pub fn session_tag(&self) -> Result<[u8; 32], String> {
+        let (_, _, master_key) = self.current_master()?;
+        Ok(session_mac(&master_key)?.finalize().into_bytes().into())
+    }
+
+    /// Checks that this keyring was derived from the same login as the tag
+    pub fn check_session_tag(&self, tag: &[u8; 32]) -> bool {
+        self.current_master()
+            .map(|(_, _, master_key)| verify_session_tag(&master_key, tag))
+            .unwrap_or(false)
+    }
+
+    /// Returns how new files are encrypted
+    pub fn options(&self) -> EncryptionOptions {
+        *self.options.lock().unwrap()
+    }
+
+    /// Changes how new files are encrypted, the existing files are not changed
+    pub fn set_options(&self, options: EncryptionOptions) {
+        *self.options.lock().unwrap() = options;
+    }
+
+    /// Keeps the data key of an opened vault
+    pub fn add_vault(
+        &self,
+        id: [u8; VAULT_ID_LEN],
+        root: PathBuf,
+        key: SecretKey,
+        encrypt_names: bool,
+        cipher: Cipher,
+    ) -> Result<(), String> {
+        let name_key = NameKey::derive(&key)?;
+        let identity_key = IdentityKey::derive(&key)?;
+
+        let mut vaults = self.vaults.lock().unwrap();
+        vaults.retain(|vault| vault.root != root);
+        vaults.push(VaultKey {
+            id,
+            root,
+            key,
+            name_key,
+            identity_key,
+            encrypt_names,
+            cipher,
+        });
+        Ok(())
+    }
+
+    /// Changes whether the new names of an opened vault are encrypted
+    pub fn set_encrypt_names(&self, root: &Path, encrypt_names: bool) {
+        let mut vaults = self.vaults.lock().unwrap();
+        if let Some(vault) = vaults.iter_mut().find(|vault| vault.root == root) {
+            vault.encrypt_names = encrypt_names;
+        }
+    }
+
+    /// Changes the cipher of the new files of an opened vault
+    pub fn set_vault_cipher(&self, root: &Path, cipher: Cipher) {
+        let mut vaults = self.vaults.lock().unwrap();
+        if let Some(vault) = vaults.iter_mut().find(|vault| vault.root == root) {
+            vault.cipher = cipher;
+        }
+    }
+
+    /// Forgets the data key of a vault when it is closed
+    pub fn remove_vault(&self, root: &Path) {
+        self.vaults
+            .lock()
+            .unwrap()
+            .retain(|vault| vault.root != root);
+    }
+
+    /// Returns the data key of an opened vault by its id
+    pub fn vault_key(&self, id: &[u8; VAULT_ID_LEN]) -> Option<SecretKey> {
+        self.vaults
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|vault| &vault.id == id)
+            .map(|vault| vault.key.clone())
+    }
+
+    /// Returns the id, data key and cipher of the opened vault that contains the path.
+    /// If vaults are nested the innermost one is used.
+    pub fn vault_for_path(&self, path: &Path) -> Option<([u8; VAULT_ID_LEN], SecretKey, Cipher)> {
+        self.vaults
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|vault| path.starts_with(&vault.root))
+            .max_by_key(|vault| vault.root.components().count())
+            .map(|vault| (vault.id, vault.key.clone(), vault.cipher))
+    }
+
+    /// Returns the identity tag of a file from its location in the opened vault that contains it.
+    /// The files inside the .lockd folder of the vault are not tagged.
+    pub fn identity_tag(&self, path: &Path) -> Result<Option<[u8; IDENTITY_TAG_LEN]>, String> {
+        let vaults = self.vaults.lock().unwrap();
+        let Some(vault) = vaults
+            .iter()
+            .filter(|vault| path.starts_with(&vault.root))
+            .max_by_key(|vault| vault.root.components().count())
+        else {
+            return Ok(None);
+        };
+
+        let relative_path = path.strip_prefix(&vault.root).unwrap_or(path);
+        if relative_path.starts_with(".lockd") {
+            return Ok(None);
+        }
+
+        // Use the same separator on every platform so synced vaults keep working
+        let relative_path = relative_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        vault.identity_key.tag(&relative_path).map(Some)
+    }
+
+    /// Returns the name key of the opened vault that contains the path,
+    /// whether new names are encrypted and the root of the vault
+    pub fn name_key_for_path(&self, path: &Path) -> Option<(NameKey, bool, PathBuf)> {
+        self.vaults
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|vault| path.starts_with(&vault.root))
+            .max_by_key(|vault| vault.root.components().count())
+            .map(|vault| {
+                (
+                    vault.name_key.clone(),
+                    vault.encrypt_names,
+                    vault.root.clone(),
+                )
+            })
+    }
+}
+
+/// MAC of the master key of a login, the tag doesn't reveal the key
+fn session_mac(master_key: &[u8; 32]) -> Result<Hmac<Sha256>, String> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(master_key)
+        .map_err(|e| format!("Failed to create session tag: {}", e))?;
+    mac.update(SESSION_TAG_INFO);
+    Ok(mac)
+}
+
+fn verify_session_tag(master_key: &[u8; 32], tag: &[u8; 32]) -> bool {
+    session_mac(master_key)
+        .map(|mac| mac.verify_slice(tag).is_ok())
+        .unwrap_or(false)
+}
@@ -0,0 +1,789 @@
+use crate::lock_ops::load_idle_timeout;
+use crate::state::AppState;
+use aes_gcm::{
+    aead::{self, Aead, Payload},
+    Aes256Gcm, KeyInit,
+};
+use chacha20poly1305::XChaCha20Poly1305;
+use flate2::{write::DeflateDecoder, write::DeflateEncoder, Compression};
+use rand;
+use std::io::{Chain, Cursor, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, State};
+use zeroize::Zeroizing;
+
+pub mod base32;
+pub mod compression;
+pub mod header;
+pub mod identity;
+pub mod kdf;
+pub mod keyfile;
+pub mod keyring;
+pub mod names;
+pub mod options;
+pub mod padding;
+pub mod recovery;
+pub mod salts;
+pub mod secret;
+pub mod stream;
+
+use header::{Cipher, FileHeader, Kdf, KdfParams, MAX_HEADER_LEN};
+use identity::IDENTITY_TAG_LEN;
+use kdf::{derive_file_key, SALT_LEN};
+use keyfile::VAULT_ID_LEN;
+use keyring::Keyring;
+use options::EncryptionOptions;
+use secret::SecretKey;
+use stream::{StreamDecryptor, StreamEncryptor};
+
+/// Error of the files whose identity tag doesn't match their location
+pub const TAMPER_ERROR: &str = "The file doesn't match its location in the vault, \
+    it was moved or renamed outside of the app or replaced with the content of another file";
+
+/// Error of the files of a vault that fail to decrypt.
+/// The password is checked when the vault is unlocked, so the key is right and the file is damaged.
+pub const CORRUPTED_ERROR: &str =
+    "The file is damaged or was modified outside of the app, it can't be decrypted";
+
+/// Create a cipher instance from the encryption key
+fn create_cipher<A: KeyInit>(key: &[u8; 32]) -> Result<A, String> {
+    A::new_from_slice(key).map_err(|e| format!("Failed to create cipher: {}", e))
+}
+
+/// Encrypt a message with a random nonce and return nonce + encrypted content
+fn encrypt_message(
+    cipher: Cipher,
+    key: &[u8; 32],
+    msg: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, String> {
+    match cipher {
+        Cipher::Aes256Gcm => seal_message(create_cipher::<Aes256Gcm>(key)?, msg, aad),
+        Cipher::XChaCha20Poly1305 => {
+            seal_message(create_cipher::<XChaCha20Poly1305>(key)?, msg, aad)
+        }
+    }
+}
+
+fn seal_message<A: Aead>(cipher: A, msg: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    // Generate a random nonce for this encryption
+    let mut nonce = aead::Nonce::<A>::default();
+    rand::fill(nonce.as_mut_slice());
+
+    let encrypted_content = cipher
+        .encrypt(&nonce, Payload { msg, aad })
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+
+    Ok([nonce.as_slice(), &encrypted_content].concat())
+}
+
+/// Decrypt nonce + encrypted content with the given cipher
+fn decrypt_message(
+    cipher: Cipher,
+    key: &[u8; 32],
+    data: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, String> {
+    // Extract nonce and encrypted content
+    if data.len() < cipher.nonce_len() {
+        return Err("File is too short".to_string());
+    }
+    let (nonce, encrypted_content) = data.split_at(cipher.nonce_len());
+    let payload = Payload {
+        msg: encrypted_content,
+        aad,
+    };
+
+    match cipher {
+        Cipher::Aes256Gcm => create_cipher::<Aes256Gcm>(key)?
+            .decrypt(aead::Nonce::<Aes256Gcm>::from_slice(nonce), payload),
+        Cipher::XChaCha20Poly1305 => create_cipher::<XChaCha20Poly1305>(key)?
+            .decrypt(aead::Nonce::<XChaCha20Poly1305>::from_slice(nonce), payload),
+    }
+    .map_err(|e| format!("Decryption failed: {}, is it the correct password?", e))
+}
+
+/// Encrypt data with a new key for this file and return (header, nonce, encrypted_data).
+/// Files inside an opened vault use the data key of the vault, other files the master key of the session.
+/// The files of a vault are also tagged with their location.
+pub fn encrypt_data(key: &Keyring, path: &Path, data: &[u8]) -> Result<Vec<u8>, String> {
+    let identity = key.identity_tag(path)?;
+    seal_data(key, path, data, identity, false)
+}
+
+/// Encrypt everything read from the reader in chunks and write the file
+/// (header + nonce prefix + chunks) to the writer, only a few chunks are kept in memory.
+/// The content is compressed first if it is set in the options.
+/// The progress is called with the number of bytes read so far.
+pub fn encrypt_stream_to(
+    key: &Keyring,
+    path: &Path,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    progress: impl FnMut(u64),
+) -> Result<(), String> {
+    let (header, file_key) = new_file_key(key, path, key.identity_tag(path)?)?;
+    let cipher = header.cipher;
+    let compress = key.options().compress;
+    let header = match compress {
+        true => header.streamed().compressed(),
+        false => header.streamed(),
+    }
+    .to_bytes();
+
+    writer
+        .write_all(&header)
+        .map_err(|e| format!("Failed to write file: {}", e))?;
+    let mut encryptor = StreamEncryptor::new(cipher, &file_key, &header, writer)?;
+
+    // The progress still counts the bytes read, not the compressed ones
+    if compress {
+        let mut encoder = DeflateEncoder::new(&mut encryptor, Compression::default());
+        stream::copy_stream(reader, &mut encoder, progress)?;
+        encoder
+            .finish()
+            .map_err(|e| format!("Compression failed: {}", e))?;
+    } else {
+        stream::copy_stream(reader, &mut encryptor, progress)?;
+    }
+
+    encryptor.finish().map(|_| ())
+}
+
+/// Decrypt a file read from the reader into the writer, checking its location like `decrypt_data`.
+/// Files encrypted in chunks are decrypted a chunk at a time, the others are read into memory.
+/// The progress is called with the number of bytes read so far.
+pub fn decrypt_stream_to(
+    key: &Keyring,
+    path: &Path,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    mut progress: impl FnMut(u64),
+) -> Result<(), String> {
+    // The header has a variable length, read enough for the longest one
+    let mut prefix = vec![0u8; MAX_HEADER_LEN];
+    let prefix_len = stream::read_full(reader, &mut prefix)?;
+    prefix.truncate(prefix_len);
+
+    match FileHeader::parse(&prefix) {
+        Ok(Some((header, header_len))) if header.is_streamed() => {
+            check_identity(key, path, header.identity)?;
+            let file_key = file_key(key, &header)?;
+
+            // The chunks start in the bytes that were read with the header
+            let (header_bytes, body_start) = prefix.split_at(header_len);
+            let mut reader = body_start.chain(reader);
+            let progress = |done| progress(header_len as u64 + done);
+
+            if !header.is_compressed() {
+                return stream::decrypt_stream(
+                    header.cipher,
+                    &file_key,
+                    header_bytes,
+                    &mut reader,
+                    writer,
+                    progress,
+                )
+                .map_err(|err| stream_error(&header, err));
+            }
+            let mut decoder = DeflateDecoder::new(writer);
+            stream::decrypt_stream(
+                header.cipher,
+                &file_key,
+                header_bytes,
+                &mut reader,
+                &mut decoder,
+                progress,
+            )
+            .map_err(|err| stream_error(&header, err))?;
+            decoder
+                .finish()
+                .map(|_| ())
+                .map_err(|e| format!("Decompression failed: {}", e))
+        }
+        _ => {
+            let mut file_data = prefix;
+            reader
+                .read_to_end(&mut file_data)
+                .map_err(|e| format!("Failed to read file: {}", e))?;
+
+            let content = Zeroizing::new(decrypt_data(key, path, &file_data)?);
+            writer
+                .write_all(&content)
+                .map_err(|e| format!("Failed to write file: {}", e))?;
+            progress(file_data.len() as u64);
+            Ok(())
+        }
+    }
+}
+
+/// Writer that encrypts what is written to it as a new file at the path, in chunks.
+/// The header is written first, `finish` must be called at the end.
+/// The content is never compressed, the writer can compress it itself.
+pub fn stream_encryptor<W: Write>(
+    key: &Keyring,
+    path: &Path,
+    mut writer: W,
+) -> Result<StreamEncryptor<W>, String> {
+    let (header, file_key) = new_file_key(key, path, key.identity_tag(path)?)?;
+    let cipher = header.cipher;
+    let header = header.streamed().to_bytes();
+
+    writer
+        .write_all(&header)
+        .map_err(|e| format!("Failed to write file: {}", e))?;
+    StreamEncryptor::new(cipher, &file_key, &header, writer)
+}
+
+/// Reader of the rest of a file after its header, starting with the bytes read with it
+pub type AfterHeader<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// Reader that decrypts a file encrypted in chunks, checking its location like `decrypt_data`
+pub fn stream_decryptor<R: Read>(
+    key: &Keyring,
+    path: &Path,
+    mut reader: R,
+) -> Result<StreamDecryptor<AfterHeader<R>>, String> {
+    let mut prefix = vec![0u8; MAX_HEADER_LEN];
+    let prefix_len = stream::read_full(&mut reader, &mut prefix)?;
+    prefix.truncate(prefix_len);
+
+    let (header, header_len) = match FileHeader::parse(&prefix)? {
+        Some((header, header_len)) if header.is_streamed() && !header.is_compressed() => {
+            (header, header_len)
+        }
+        _ => return Err("The file is not encrypted in chunks".to_string()),
+    };
+    check_identity(key, path, header.identity)?;
+    let file_key = file_key(key, &header)?;
+
+    // The chunks start in the bytes that were read with the header
+    let body_start = prefix.split_off(header_len);
+    StreamDecryptor::new(
+        header.cipher,
+        &file_key,
+        &prefix,
+        Cursor::new(body_start).chain(reader),
+    )
+}
+
+/// Derive a new key for a file at the path and the header that describes it
+fn new_file_key(
+    key: &Keyring,
+    path: &Path,
+    identity: Option<[u8; IDENTITY_TAG_LEN]>,
+) -> Result<(FileHeader, SecretKey), String> {
+    // Derive a key only for this file
+    let file_salt: [u8; SALT_LEN] = rand::random();
+    let (cipher, kdf, kdf_params, mut salt, file_key) = match key.vault_for_path(path) {
+        Some((vault_id, vault_key, cipher)) => (
+            cipher,
+            Kdf::VaultHkdf,
+            KdfParams::UNUSED,
+            vault_id.to_vec(),
+            derive_file_key(&vault_key, &file_salt)?,
+        ),
+        // The files outside of vaults always use the default cipher
+        None => {
+            let (master_salt, kdf_params, master_key) = key.current_master()?;
+            (
+                Cipher::default(),
+                Kdf::Argon2idHkdf,
+                kdf_params,
+                master_salt.to_vec(),
+                derive_file_key(&master_key, &file_salt)?,
+            )
+        }
+    };
+
+    // Describe how the file is encrypted so it can be read by future versions
+    salt.extend_from_slice(&file_salt);
+    let header = FileHeader::new(cipher, kdf, kdf_params, salt, identity);
+
+    Ok((header, file_key))
+}
+
+/// Encrypt the data of a file with the given identity tag, in chunks if it is streamed.
+/// The data is compressed if it is set in the options and it gets smaller,
+/// and files encrypted at once are padded if it is set in the options.
+fn seal_data(
+    key: &Keyring,
+    path: &Path,
+    data: &[u8],
+    identity: Option<[u8; IDENTITY_TAG_LEN]>,
+    streamed: bool,
+) -> Result<Vec<u8>, String> {
+    let (mut header, file_key) = new_file_key(key, path, identity)?;
+    let cipher = header.cipher;
+    let options = key.options();
+
+    // Compress before padding, the padding would only make it longer.
+    // The copies of the content are wiped once encrypted.
+    let compressed = match options.compress {
+        true => Some(Zeroizing::new(compression::compress(data)?)).filter(|c| c.len() < data.len()),
+        false => None,
+    };
+    let data = match &compressed {
+        Some(compressed) => {
+            header = header.compressed();
+            compressed
+        }
+        None => data,
+    };
+
+    if streamed {
+        let header = header.streamed().to_bytes();
+        let mut file_data = header.clone();
+        stream::encrypt_stream(
+            cipher,
+            &file_key,
+            &header,
+            &mut &data[..],
+            &mut file_data,
+            |_| {},
+        )?;
+        return Ok(file_data);
+    }
+
+    // Hide the length of the content, the header records it so the padding can be removed
+    let padded = options
+        .pad_notes
+        .then(|| Zeroizing::new(padding::pad(data)));
+    let (header, data) = match &padded {
+        Some(padded) => (header.padded().to_bytes(), &padded[..]),
+        None => (header.to_bytes(), data),
+    };
+
+    // Encrypt the content, the header is authenticated with it
+    let encrypted_content = encrypt_message(cipher, &file_key, data, &header)?;
+
+    // Create the data to save (header + nonce + encrypted content)
+    Ok([header, encrypted_content].concat())
+}
+
+/// Decrypt data with the given keys from file data (header + nonce + encrypted content),
+/// checking that a tagged file is at the path it was written for.
+/// Files without a header (nonce + encrypted content) are still supported.
+pub fn decrypt_data(key: &Keyring, path: &Path, file_data: &[u8]) -> Result<Vec<u8>, String> {
+    let (content, identity) = open_data(key, file_data)?;
+    check_identity(key, path, identity)?;
+
+    Ok(content)
+}
+
+/// Check that a tagged file is at the path it was written for
+fn check_identity(
+    key: &Keyring,
+    path: &Path,
+    identity: Option<[u8; IDENTITY_TAG_LEN]>,
+) -> Result<(), String> {
+    if identity.is_some() && identity != key.identity_tag(path)? {
+        return Err(TAMPER_ERROR.to_string());
+    }
+    Ok(())
+}
+
+/// Decrypt data without checking its location, for files the user accepts at a new location
+pub fn decrypt_data_unchecked(key: &Keyring, file_data: &[u8]) -> Result<Vec<u8>, String> {
+    open_data(key, file_data).map(|(content, _)| content)
+}
+
+/// Encrypt a file that the app moved inside its vault again, from the reader into the writer,
+/// so it is tagged with its new location. It must be tagged with the old one.
+/// Only the keys and the identity change, see `reseal_stream_to`.
+pub fn retag_stream_to(
+    key: &Keyring,
+    path: &Path,
+    new_path: &Path,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+) -> Result<(), String> {
+    reseal_stream_to(key, new_path, reader, writer, |identity| {
+        check_identity(key, path, identity)?;
+        key.identity_tag(new_path)
+    })
+}
+
+/// Encrypt a file again from the reader into the writer with the current keys of its path.
+/// Files without an identity tag get the one of the path, the copies of a note in its history
+/// keep the tag of their note.
+pub fn reencrypt_stream_to(
+    key: &Keyring,
+    path: &Path,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+) -> Result<(), String> {
+    reseal_stream_to(key, path, reader, writer, |identity| match identity {
+        Some(identity) => Ok(Some(identity)),
+        None => key.identity_tag(path),
+    })
+}
+
+/// Encrypt a file again with the keys of the path and the identity tag given for its current one.
+/// Files encrypted in chunks are encrypted again a chunk at a time, so large files fit in memory.
+/// The content is not unpadded or decompressed, so the stream, padding and compression flags
+/// are kept. Files without a header, or with the first one, get the current options.
+fn reseal_stream_to(
+    key: &Keyring,
+    path: &Path,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    identity: impl FnOnce(
+        Option<[u8; IDENTITY_TAG_LEN]>,
+    ) -> Result<Option<[u8; IDENTITY_TAG_LEN]>, String>,
+) -> Result<(), String> {
+    // The header has a variable length, read enough for the longest one
+    let mut prefix = vec![0u8; MAX_HEADER_LEN];
+    let prefix_len = stream::read_full(reader, &mut prefix)?;
+    prefix.truncate(prefix_len);
+
+    let (header, header_len) = match FileHeader::parse(&prefix) {
+        Ok(Some((header, header_len))) if header.is_streamed() => (header, header_len),
+        // The other files are a single message, they are read at once
+        _ => {
+            let mut file_data = prefix;
+            reader
+                .read_to_end(&mut file_data)
+                .map_err(|e| format!("Failed to read file: {}", e))?;
+            let file_data = reseal_message(key, path, &file_data, identity)?;
+            return writer
+                .write_all(&file_data)
+                .map_err(|e| format!("Failed to write file: {}", e));
+        }
+    };
+
+    let file_key = file_key(key, &header)?;
+    let (new_header, new_key) = new_file_key(key, path, identity(header.identity)?)?;
+    let cipher = new_header.cipher;
+    let new_header = new_header.with_content_flags(&header).to_bytes();
+
+    writer
+        .write_all(&new_header)
+        .map_err(|e| format!("Failed to write file: {}", e))?;
+    let mut encryptor = StreamEncryptor::new(cipher, &new_key, &new_header, writer)?;
+
+    // The chunks start in the bytes that were read with the header
+    let (header_bytes, body_start) = prefix.split_at(header_len);
+    stream::decrypt_stream(
+        header.cipher,
+        &file_key,
+        header_bytes,
+        &mut body_start.chain(reader),
+        &mut encryptor,
+        |_| {},
+    )
+    .map_err(|err| stream_error(&header, err))?;
+
+    encryptor.finish().map(|_| ())
+}
+
+/// Encrypt a file made of a single message again, see `reseal_stream_to`
+fn reseal_message(
+    key: &Keyring,
+    path: &Path,
+    file_data: &[u8],
+    identity: impl FnOnce(
+        Option<[u8; IDENTITY_TAG_LEN]>,
+    ) -> Result<Option<[u8; IDENTITY_TAG_LEN]>, String>,
+) -> Result<Vec<u8>, String> {
+    if let Ok(Some((header, header_len))) = FileHeader::parse(file_data) {
+        let (header_bytes, body) = file_data.split_at(header_len);
+        let content = file_key(key, &header)
+            .and_then(|file_key| decrypt_message(header.cipher, &file_key, body, header_bytes));
+
+        // The content is encrypted again as it is, padded or compressed
+        if let (true, Ok(content)) = (header.version >= 2, content) {
+            let content = Zeroizing::new(content);
+            let (new_header, new_key) = new_file_key(key, path, identity(header.identity)?)?;
+            let cipher = new_header.cipher;
+            let new_header = new_header.with_content_flags(&header).to_bytes();
+            let encrypted_content = encrypt_message(cipher, &new_key, &content, &new_header)?;
+            return Ok([new_header, encrypted_content].concat());
+        }
+    }
+
+    // Legacy files are opened like any file, with the fallbacks of `open_data`
+    let (content, old_identity) = open_data(key, file_data)?;
+    let content = Zeroizing::new(content);
+    seal_data(key, path, &content, identity(old_identity)?, false)
+}
+
+/// Check that the keys of the session open a file on disk, without checking its location.
+/// Only the first chunk of a file encrypted in chunks is decrypted, the other files are
+/// a single message and are decrypted whole.
+pub fn check_file_key(key: &Keyring, path: &Path) -> Result<(), String> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to read file {}: {}", path.display(), e))?;
+    let mut prefix = vec![0u8; MAX_HEADER_LEN];
+    let prefix_len = stream::read_full(&mut file, &mut prefix)?;
+    prefix.truncate(prefix_len);
+
+    match FileHeader::parse(&prefix) {
+        Ok(Some((header, header_len))) if header.is_streamed() => {
+            let file_key = file_key(key, &header)?;
+            let (header_bytes, body_start) = prefix.split_at(header_len);
+            let mut decryptor = StreamDecryptor::new(
+                header.cipher,
+                &file_key,
+                header_bytes,
+                body_start.chain(file),
+            )?;
+            decryptor
+                .next_chunk()
+                .map(drop)
+                .map_err(|err| stream_error(&header, err))
+        }
+        _ => {
+            let mut file_data = prefix;
+            file.read_to_end(&mut file_data)
+                .map_err(|e| format!("Failed to read file: {}", e))?;
+            open_data(key, &file_data).map(|(content, _)| drop(Zeroizing::new(content)))
+        }
+    }
+}
+
+/// Read the start of a file on disk, long enough for the longest header
+fn read_header_prefix(path: &Path) -> Result<Vec<u8>, String> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to read file {}: {}", path.display(), e))?;
+    let mut prefix = vec![0u8; MAX_HEADER_LEN];
+    let prefix_len = stream::read_full(&mut file, &mut prefix)?;
+    prefix.truncate(prefix_len);
+    Ok(prefix)
+}
+
+/// Get the identity tag of a file on disk, only its header is read
+pub fn read_file_identity(path: &Path) -> Result<Option<[u8; IDENTITY_TAG_LEN]>, String> {
+    Ok(file_identity(&read_header_prefix(path)?))
+}
+
+/// Get the id of the vault whose data key encrypted a file on disk, only its header is read
+pub fn read_file_vault_id(path: &Path) -> Result<Option<[u8; VAULT_ID_LEN]>, String> {
+    Ok(file_vault_id(&read_header_prefix(path)?))
+}
+
+/// Get the identity tag of a file, if it has one
+pub fn file_identity(file_data: &[u8]) -> Option<[u8; IDENTITY_TAG_LEN]> {
+    match FileHeader::parse(file_data) {
+        Ok(Some((header, _))) => header.identity,
+        _ => None,
+    }
+}
+
+/// Decrypt the data of a file and return its content and identity tag
+fn open_data(
+    key: &Keyring,
+    file_data: &[u8],
+) -> Result<(Vec<u8>, Option<[u8; IDENTITY_TAG_LEN]>), String> {
+    // Open the file if it is empty
+    if file_data.is_empty() {
+        return Ok((Vec::new(), None));
+    }
+
+    match FileHeader::parse(file_data) {
+        // The nonce of a legacy file could start with the magic bytes by chance,
+        // so fall back to the legacy format before giving up
+        Ok(Some((header, header_len))) => {
+            let (header_bytes, body) = file_data.split_at(header_len);
+            match decrypt_with_header(key, &header, header_bytes, body) {
+                Ok(content) => Ok((content, header.identity)),
+                Err(err) => fallback_legacy(key, file_data, err),
+            }
+        }
+        Ok(None) => decrypt_legacy(key, file_data).map(|content| (content, None)),
+        Err(err) => fallback_legacy(key, file_data, err),
+    }
+}
+
+/// Decrypt the body of a file (nonce + encrypted content) as described by its header
+fn decrypt_with_header(
+    key: &Keyring,
+    header: &FileHeader,
+    header_bytes: &[u8],
+    body: &[u8],
+) -> Result<Vec<u8>, String> {
+    let file_key = file_key(key, header)?;
+
+    // The first version didn't authenticate the header
+    let aad = if header.version >= 2 {
+        header_bytes
+    } else {
+        &[]
+    };
+
+    let content = match header.is_streamed() {
+        true => {
+            let mut content = Vec::new();
+            stream::decrypt_stream(
+                header.cipher,
+                &file_key,
+                aad,
+                &mut &body[..],
+                &mut content,
+                |_| {},
+            )
+            .map(|_| content)
+        }
+        false => decrypt_message(header.cipher, &file_key, body, aad),
+    };
+
+    // Since the header is authenticated, a change anywhere in the file is caught here
+    let content = content.map_err(|err| match header.version {
+        1 => err,
+        _ => decryption_error(
+            header,
+            "Decryption failed, the file was modified or it is not the correct password",
+        ),
+    })?;
+
+    let content = match header.is_padded() {
+        true => padding::unpad(content)?,
+        false => content,
+    };
+    match header.is_compressed() {
+        true => compression::decompress(&Zeroizing::new(content)),
+        false => Ok(content),
+    }
+}
+
+/// Error of a file that fails to decrypt. Only the files of a vault have a verified key,
+/// for the others it can also be the wrong password, which is the given error.
+fn decryption_error(header: &FileHeader, err: &str) -> String {
+    match header.kdf {
+        Kdf::VaultHkdf => CORRUPTED_ERROR.to_string(),
+        _ => err.to_string(),
+    }
+}
+
+/// Error of a file encrypted in chunks that fails, the others are kept as is
+fn stream_error(header: &FileHeader, err: String) -> String {
+    match err.as_str() {
+        stream::CHUNK_ERROR => decryption_error(header, &err),
+        _ => err,
+    }
+}
+
+/// Get the key of a file from the key derivation described by its header
+fn file_key(key: &Keyring, header: &FileHeader) -> Result<SecretKey, String> {
+    match header.kdf {
+        Kdf::Argon2idUsername => {
+            // The username key is always derived with the default parameters
+            if header.kdf_params != KdfParams::default() {
+                return Err("Unsupported key derivation parameters".to_string());
+            }
+            key.legacy_key()
+        }
+        Kdf::Argon2idHkdf => {
+            if header.salt.len() != 2 * SALT_LEN {
+                return Err("Invalid salt in file header".to_string());
+            }
+            let (master_salt, file_salt) = header.salt.split_at(SALT_LEN);
+            let master_salt: [u8; SALT_LEN] = master_salt.try_into().unwrap();
+
+            let master_key = key.master_key(&master_salt, &header.kdf_params)?;
+            derive_file_key(&master_key, file_salt)
+        }
+        Kdf::VaultHkdf => {
+            let vault_id = header_vault_id(header).ok_or("Invalid salt in file header")?;
+            let vault_key = key
+                .vault_key(&vault_id)
+                .ok_or("Open the vault this file belongs to first")?;
+            derive_file_key(&vault_key, &header.salt[VAULT_ID_LEN..])
+        }
+    }
+}
+
+/// Get the id of the vault whose data key encrypted the file, if any
+pub fn file_vault_id(file_data: &[u8]) -> Option<[u8; VAULT_ID_LEN]> {
+    match FileHeader::parse(file_data) {
+        Ok(Some((header, _))) => header_vault_id(&header),
+        _ => None,
+    }
+}
+
+/// Get the id of the vault from the salt of a header (vault id + file salt)
+fn header_vault_id(header: &FileHeader) -> Option<[u8; VAULT_ID_LEN]> {
+    if header.kdf != Kdf::VaultHkdf || header.salt.len() != VAULT_ID_LEN + SALT_LEN {
+        return None;
+    }
+    header.salt[..VAULT_ID_LEN].try_into().ok()
+}
+
+/// Decrypt a file written before the header was introduced (nonce + encrypted content)
+fn decrypt_legacy(key: &Keyring, file_data: &[u8]) -> Result<Vec<u8>, String> {
+    let legacy_key = key.legacy_key()?;
+    decrypt_message(Cipher::Aes256Gcm, &legacy_key, file_data, &[])
+}
+
+/// Try a file that looks like it has a header as a legacy file, in case its nonce starts
+/// with the magic bytes. Only a legacy key that is already derived is tried,
+/// the password isn't asked again for such an unlikely case, so the error of the header is kept.
+fn fallback_legacy(
+    key: &Keyring,
+    file_data: &[u8],
+    err: String,
+) -> Result<(Vec<u8>, Option<[u8; IDENTITY_TAG_LEN]>), String> {
+    let legacy_key = key.derived_legacy_key().ok_or(err.clone())?;
+    decrypt_message(Cipher::Aes256Gcm, &legacy_key, file_data, &[])
+        .map(|content| (content, None))
+        .map_err(|_| err)
+}
+
+#[tauri::command]
+/// Derive the keys of the user from the username and password.
+/// New files use the given Argon2 parameters, or the default ones.
+/// There are no accounts, so any password is accepted here: it is checked by the keyfile
+/// of a vault when it is opened, or by its notes if the folder isn't a vault yet.
+pub fn derive_encryption_key(
+    username: &str,
+    password: &str,
+    kdf_params: Option<KdfParams>,
+    app_state: State<Mutex<AppState>>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    // Derive the keys before locking the state, Argon2 takes a while
+    let master_salt = salts::user_salt(&app_handle, username)?;
+    let keyring = Keyring::new(
+        username,
+        password,
+        master_salt,
+        kdf_params.unwrap_or_default(),
+    )?;
+    keyring.set_options(EncryptionOptions::load(&app_handle)?);
+    let idle_timeout = load_idle_timeout(&app_handle)?;
+
+    let mut state = app_state.lock().unwrap();
+    state.set_idle_timeout(idle_timeout);
+    state.set_key(keyring);
+
+    Ok(())
+}
+
+#[tauri::command]
+/// Derive the keys that files asked for but that weren't derived at login,
+/// after checking the password against the one of the session
+pub async fn derive_missing_keys(
+    username: String,
+    password: String,
+    app_state: State<'_, Mutex<AppState>>,
+) -> Result<(), String> {
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+
+    // Run it outside of the async runtime, there can be a few keys to derive
+    tauri::async_runtime::spawn_blocking(move || key.derive_missing_keys(&username, &password))
+        .await
+        .map_err(|e| format!("Key derivation failed: {}", e))?
+}
+
+#[tauri::command]
+/// Benchmark Argon2 and return the parameters that take about the target time (1 second by default)
+pub async fn calibrate_kdf(target_ms: Option<u64>) -> Result<KdfParams, String> {
+    let target = Duration::from_millis(target_ms.unwrap_or(1000));
+
+    // Run it outside of the async runtime, it blocks for a few seconds
+    tauri::async_runtime::spawn_blocking(move || kdf::calibrate(target))
+        .await
+        .map_err(|e| format!("Calibration failed: {}", e))?
+}
@@ -0,0 +1,165 @@
+use crate::encryption::base32;
+use crate::encryption::secret::SecretKey;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+/// Context used to derive the keys of the names from the data key of a vault
+const NAME_KEY_INFO: &[u8] = b"privatenotes file names v1";
+
+/// Associated data of the encrypted names
+const NAME_AAD: &[u8] = b"privatenotes name v1";
+
+/// Length of the nonce at the start of an encrypted name
+const NONCE_LEN: usize = 12;
+
+/// Length of the authentication tag of an encrypted name
+const TAG_LEN: usize = 16;
+
+/// Longest encoded name, so the extension fits in the limit of most file systems (255 bytes)
+const MAX_ENCODED_LEN: usize = 240;
+
+/// Keys of the encrypted names of a vault
+#[derive(Clone)]
+pub struct NameKey {
+    cipher_key: SecretKey,
+    nonce_key: SecretKey,
+}
+
+impl NameKey {
+    /// Derives the keys of the names from the data key of a vault
+    pub fn derive(data_key: &[u8; 32]) -> Result<Self, String> {
+        let mut okm = Zeroizing::new([0u8; 64]);
+        Hkdf::<Sha256>::new(None, data_key)
+            .expand(NAME_KEY_INFO, &mut okm[..])
+            .map_err(|e| format!("Failed to derive name key: {}", e))?;
+
+        Ok(NameKey {
+            cipher_key: SecretKey::from_slice(&okm[..32])?,
+            nonce_key: SecretKey::from_slice(&okm[32..])?,
+        })
+    }
+
+    /// Encrypts a name into an opaque base32 identifier that can be used as a file name.
+    /// The nonce comes from the name and the directory it is written in (relative to the vault),
+    /// so the same name gets the same identifier in a directory but a different one elsewhere,
+    /// and the disk doesn't show which items of the vault have the same name.
+    pub fn encrypt(&self, dir: &str, name: &str) -> Result<String, String> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.nonce_key[..])
+            .map_err(|e| format!("Failed to create name nonce: {}", e))?;
+        // The directory is length prefixed so it can't run into the name
+        mac.update(&(dir.len() as u64).to_le_bytes());
+        mac.update(dir.as_bytes());
+        mac.update(name.as_bytes());
+        let nonce_bytes = mac.finalize().into_bytes();
+        let nonce = Nonce::from_slice(&nonce_bytes[..NONCE_LEN]);
+
+        let cipher = Aes256Gcm::new_from_slice(&self.cipher_key[..])
+            .map_err(|e| format!("Failed to create cipher: {}", e))?;
+        let ciphertext = cipher
+            .encrypt(
+                nonce,
+                Payload {
+                    msg: name.as_bytes(),
+                    aad: NAME_AAD,
+                },
+            )
+            .map_err(|e| format!("Failed to encrypt name: {}", e))?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+
+        let encoded = base32::encode(&data);
+        if encoded.len() > MAX_ENCODED_LEN {
+            return Err("The name is too long to be encrypted".to_string());
+        }
+        Ok(encoded)
+    }
+
+    /// Decrypts a name encrypted by this key, None if it is not one
+    pub fn decrypt(&self, encoded: &str) -> Option<String> {
+        let data = base32::decode(encoded).ok()?;
+        if data.len() < NONCE_LEN + TAG_LEN {
+            return None;
+        }
+
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let cipher = Aes256Gcm::new_from_slice(&self.cipher_key[..]).ok()?;
+        let name = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: NAME_AAD,
+                },
+            )
+            .ok()?;
+
+        String::from_utf8(name).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> NameKey {
+        NameKey::derive(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = key(1);
+        for name in ["note", "Meeting notes 2024", "ñandú ☕", "a.b.c"] {
+            let encrypted = key.encrypt("folder/sub", name).unwrap();
+            assert_eq!(key.decrypt(&encrypted).as_deref(), Some(name));
+            // The disk can change the case of the names
+            assert_eq!(
+                key.decrypt(&encrypted.to_lowercase()).as_deref(),
+                Some(name)
+            );
+        }
+    }
+
+    #[test]
+    fn same_name_depends_on_directory() {
+        let key = key(1);
+        let encrypted = key.encrypt("folder", "note").unwrap();
+
+        assert_eq!(key.encrypt("folder", "note").unwrap(), encrypted);
+        assert_ne!(key.encrypt("other", "note").unwrap(), encrypted);
+        // The length prefix keeps the directory apart from the name
+        assert_ne!(
+            key.encrypt("ab", "c").unwrap(),
+            key.encrypt("a", "bc").unwrap()
+        );
+    }
+
+    #[test]
+    fn other_keys_and_plain_names_are_not_decrypted() {
+        let encrypted = key(1).encrypt("", "note").unwrap();
+
+        assert_eq!(key(2).decrypt(&encrypted), None);
+        assert_eq!(key(1).decrypt("note"), None);
+        assert_eq!(key(1).decrypt(""), None);
+    }
+
+    #[test]
+    fn modified_name_is_not_decrypted() {
+        let key = key(1);
+        let encrypted = key.encrypt("", "note").unwrap();
+
+        let mut modified: Vec<char> = encrypted.chars().collect();
+        modified[5] = if modified[5] == 'A' { 'B' } else { 'A' };
+        let modified: String = modified.into_iter().collect();
+        assert_eq!(key.decrypt(&modified), None);
+    }
+
+    #[test]
+    fn long_names_are_rejected() {
+        assert!(key(1).encrypt("", &"x".repeat(200)).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use crate::file_operations::commands::read_config;
+use tauri::AppHandle;
+
+/// How new files are encrypted, set in the security section of the app config
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EncryptionOptions {
+    /// Notes are padded before encryption to hide their exact length
+    pub pad_notes: bool,
+    /// Notes and files are compressed before encryption
+    pub compress: bool,
+}
+
+/// Config file with only the section that is read here, the frontend saves the rest
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct SecurityConfig {
+    security: EncryptionOptions,
+}
+
+impl EncryptionOptions {
+    /// Reads the options from the content of the app config,
+    /// the default options are used if it can't be parsed
+    pub fn from_config(config: &str) -> Self {
+        serde_json::from_str::<SecurityConfig>(config)
+            .unwrap_or_default()
+            .security
+    }
+
+    /// Reads the options from the app config file
+    pub fn load(app_handle: &AppHandle) -> Result<Self, String> {
+        Ok(Self::from_config(&read_config(app_handle)?))
+    }
+}
@@ -0,0 +1,90 @@
+/// Smallest padded length, so short notes all look the same
+const MIN_PADDED_LEN: u64 = 256;
+
+/// Byte that marks the end of the content, it is followed by zeros
+const PADDING_MARKER: u8 = 0x80;
+
+/// Length of the content once padded with the Padmé scheme: lengths are rounded up
+/// so only their few highest bits are kept, which leaks O(log log L) bits of the length
+/// and costs at most 12% of overhead.
+pub fn padded_len(len: u64) -> u64 {
+    let len = len.max(MIN_PADDED_LEN);
+
+    // E = floor(log2(L)), S = floor(log2(E)) + 1
+    let exponent = 63 - len.leading_zeros() as u64;
+    let exponent_bits = 64 - exponent.leading_zeros() as u64;
+    let mask = (1u64 << (exponent - exponent_bits)) - 1;
+
+    (len + mask) & !mask
+}
+
+/// Pads the content to hide its length: content | 0x80 | zeros
+pub fn pad(data: &[u8]) -> Vec<u8> {
+    let len = padded_len(data.len() as u64 + 1) as usize;
+
+    let mut padded = Vec::with_capacity(len);
+    padded.extend_from_slice(data);
+    padded.push(PADDING_MARKER);
+    padded.resize(len, 0);
+    padded
+}
+
+/// Removes the padding added by `pad`
+pub fn unpad(mut padded: Vec<u8>) -> Result<Vec<u8>, String> {
+    let end = padded
+        .iter()
+        .rposition(|&byte| byte != 0)
+        .filter(|&end| padded[end] == PADDING_MARKER)
+        .ok_or("Invalid padding")?;
+
+    padded.truncate(end);
+    Ok(padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for len in [0, 1, 254, 255, 256, 1000, 65_537, 1_000_000] {
+            let content: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let padded = pad(&content);
+
+            assert_eq!(padded.len() as u64, padded_len(len as u64 + 1));
+            assert_eq!(unpad(padded).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn content_ending_with_zeros() {
+        let content = vec![PADDING_MARKER, 0, 0];
+        assert_eq!(unpad(pad(&content)).unwrap(), content);
+    }
+
+    #[test]
+    fn padded_len_hides_small_differences() {
+        assert_eq!(padded_len(0), MIN_PADDED_LEN);
+        assert_eq!(padded_len(1), padded_len(MIN_PADDED_LEN));
+        assert_eq!(padded_len(1000), padded_len(1001));
+
+        // Padmé costs at most 12% and never shrinks the content
+        for len in (MIN_PADDED_LEN..100_000).step_by(97) {
+            let padded = padded_len(len);
+            assert!(padded >= len);
+            assert!(padded - len <= len * 12 / 100);
+            assert!(padded_len(len + 1) >= padded);
+        }
+    }
+
+    #[test]
+    fn invalid_padding_is_rejected() {
+        assert!(unpad(Vec::new()).is_err());
+        assert!(unpad(vec![0; 256]).is_err());
+
+        let mut padded = pad(b"note");
+        let marker = padded.iter().rposition(|&byte| byte != 0).unwrap();
+        padded[marker] = 0x01;
+        assert!(unpad(padded).is_err());
+    }
+}
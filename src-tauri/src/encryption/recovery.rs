@@ -0,0 +1,60 @@
+use crate::encryption::base32;
+use crate::encryption::keyfile::VAULT_ID_LEN;
+use crate::encryption::secret::SecretKey;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+/// Number of random bytes in a recovery code (160 bits)
+pub const RECOVERY_CODE_LEN: usize = 20;
+
+/// Context used to derive the key that wraps the data key from a recovery code
+const RECOVERY_KEY_INFO: &[u8] = b"privatenotes recovery key v1";
+
+/// Characters between the dashes of the printed code
+const GROUP_LEN: usize = 4;
+
+/// Generates a new random recovery code and returns (code, printable code)
+pub fn generate_recovery_code() -> ([u8; RECOVERY_CODE_LEN], String) {
+    let code: [u8; RECOVERY_CODE_LEN] = rand::random();
+    (code, format_recovery_code(&code))
+}
+
+/// Encodes a recovery code in base32, in groups of 4 characters separated by dashes
+pub fn format_recovery_code(code: &[u8; RECOVERY_CODE_LEN]) -> String {
+    // 20 bytes are exactly 32 characters
+    let chars: Vec<char> = base32::encode(code).chars().collect();
+
+    chars
+        .chunks(GROUP_LEN)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Decodes a recovery code typed by the user, ignoring dashes, spaces and case
+pub fn parse_recovery_code(text: &str) -> Result<[u8; RECOVERY_CODE_LEN], String> {
+    let text: String = text
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect();
+
+    let code =
+        base32::decode(&text).map_err(|c| format!("Invalid character '{}' in recovery code", c))?;
+
+    code.try_into()
+        .map_err(|_| "The recovery code must have 32 characters".to_string())
+}
+
+/// Derives the key that wraps the data key of a vault from its recovery code.
+/// The code is random so it doesn't need a slow key derivation.
+pub fn derive_recovery_key(
+    code: &[u8; RECOVERY_CODE_LEN],
+    vault_id: &[u8; VAULT_ID_LEN],
+) -> Result<SecretKey, String> {
+    let mut key = SecretKey::zeroed();
+    Hkdf::<Sha256>::new(Some(vault_id), code)
+        .expand(RECOVERY_KEY_INFO, &mut key[..])
+        .map_err(|e| format!("Failed to derive recovery key: {}", e))?;
+
+    Ok(key)
+}
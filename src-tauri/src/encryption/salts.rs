@@ -0,0 +1,48 @@
+use crate::encryption::kdf::SALT_LEN;
+use crate::file_operations::commands::config_dir_file;
+use crate::file_operations::fs_utils::write_atomic;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use tauri::AppHandle;
+
+/// File of the master salts of the users, in the app config directory
+const SALTS_FILE: &str = "salts.json";
+
+/// Context of the hash of the usernames, so the file doesn't list them
+const USER_ID_INFO: &[u8] = b"privatenotes user id v1";
+
+/// Returns the salt of the master key of a user, it is created at their first login.
+/// Every file the user writes on this device uses it, so the key derived at login opens them all.
+pub fn user_salt(app_handle: &AppHandle, username: &str) -> Result<[u8; SALT_LEN], String> {
+    let path = config_dir_file(app_handle, SALTS_FILE)?;
+    let mut salts: HashMap<String, String> = match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            serde_json::from_str(&content).map_err(|e| format!("Invalid salts file: {}", e))?
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+        Err(e) => return Err(format!("Failed to read salts file: {}", e)),
+    };
+
+    let user_id = hex::encode(
+        Sha256::new()
+            .chain_update(USER_ID_INFO)
+            .chain_update(username.as_bytes())
+            .finalize(),
+    );
+
+    if let Some(salt) = salts.get(&user_id) {
+        return hex::decode(salt)
+            .ok()
+            .and_then(|salt| salt.try_into().ok())
+            .ok_or("Invalid salt in salts file".to_string());
+    }
+
+    let salt: [u8; SALT_LEN] = rand::random();
+    salts.insert(user_id, hex::encode(salt));
+    let content = serde_json::to_string_pretty(&salts)
+        .map_err(|e| format!("Failed to serialize salts file: {}", e))?;
+    write_atomic(&path, content.as_bytes())?;
+
+    Ok(salt)
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use zeroize::Zeroize;
+
+/// 256-bit key that is wiped from memory when it is dropped.
+/// It lives in its own allocation so moving it doesn't leave copies behind,
+/// and on Linux that memory is locked so it is never written to swap.
+/// It isn't Copy, a copy has to be made explicitly with `clone`.
+pub struct SecretKey(Box<[u8; 32]>);
+
+impl SecretKey {
+    /// A key of zeros, to be filled in place by a key derivation
+    pub fn zeroed() -> Self {
+        let key = SecretKey(Box::new([0u8; 32]));
+        lock_memory(key.0.as_ref());
+        key
+    }
+
+    /// A random key
+    pub fn random() -> Self {
+        let mut key = SecretKey::zeroed();
+        rand::fill(&mut key.0[..]);
+        key
+    }
+
+    /// Copies the key from a buffer, which should be wiped by the caller
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != 32 {
+            return Err("Invalid key length".to_string());
+        }
+        let mut key = SecretKey::zeroed();
+        key.0.copy_from_slice(bytes);
+        Ok(key)
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8; 32];
+
+    fn deref(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretKey {
+    fn deref_mut(&mut self) -> &mut [u8; 32] {
+        &mut self.0
+    }
+}
+
+impl Clone for SecretKey {
+    fn clone(&self) -> Self {
+        let mut key = SecretKey::zeroed();
+        key.0.copy_from_slice(&self.0[..]);
+        key
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Never print the key, even in debug output
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+/// Keeps the memory of a key out of swap. It is only a hardening, the key still works
+/// if the limit of locked memory of the process is reached, so failures are ignored.
+/// Locks apply to whole pages and aren't counted, so the page is never unlocked:
+/// other keys can share it.
+#[cfg(target_os = "linux")]
+fn lock_memory(bytes: &[u8; 32]) {
+    unsafe {
+        libc::mlock(bytes.as_ptr().cast(), bytes.len());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lock_memory(_bytes: &[u8; 32]) {}
@@ -0,0 +1,484 @@
+use crate::encryption::header::Cipher;
+use aes_gcm::{
+    aead::{
+        generic_array::GenericArray,
+        stream::{DecryptorBE32, EncryptorBE32},
+        Payload,
+    },
+    Aes256Gcm, KeyInit,
+};
+use chacha20poly1305::XChaCha20Poly1305;
+use std::io::{self, Read, Write};
+use zeroize::Zeroizing;
+
+/// Length of the plaintext of every chunk but the last one
+pub const STREAM_CHUNK_LEN: usize = 1024 * 1024;
+
+/// Length of the nonce prefix written before the chunks, the rest of the nonce
+/// is the counter of the chunk and the flag of the last chunk
+pub fn stream_nonce_len(cipher: Cipher) -> usize {
+    cipher.nonce_len() - 5
+}
+
+/// Length of the authentication tag of every chunk
+const TAG_LEN: usize = 16;
+
+/// Error of a chunk that fails to decrypt
+pub const CHUNK_ERROR: &str = "Decryption failed, the file was modified or truncated, \
+    or it is not the correct password";
+
+/// STREAM encryptor of the cipher of the file
+enum Encryptor {
+    Aes256Gcm(EncryptorBE32<Aes256Gcm>),
+    XChaCha20Poly1305(EncryptorBE32<XChaCha20Poly1305>),
+}
+
+impl Encryptor {
+    fn new(cipher: Cipher, key: &[u8; 32], nonce: &[u8]) -> Result<Self, String> {
+        Ok(match cipher {
+            Cipher::Aes256Gcm => Encryptor::Aes256Gcm(EncryptorBE32::from_aead(
+                Aes256Gcm::new_from_slice(key)
+                    .map_err(|e| format!("Failed to create cipher: {}", e))?,
+                GenericArray::from_slice(nonce),
+            )),
+            Cipher::XChaCha20Poly1305 => Encryptor::XChaCha20Poly1305(EncryptorBE32::from_aead(
+                XChaCha20Poly1305::new_from_slice(key)
+                    .map_err(|e| format!("Failed to create cipher: {}", e))?,
+                GenericArray::from_slice(nonce),
+            )),
+        })
+    }
+
+    fn encrypt_next(&mut self, payload: Payload) -> Result<Vec<u8>, String> {
+        match self {
+            Encryptor::Aes256Gcm(encryptor) => encryptor.encrypt_next(payload),
+            Encryptor::XChaCha20Poly1305(encryptor) => encryptor.encrypt_next(payload),
+        }
+        .map_err(|e| format!("Encryption failed: {}", e))
+    }
+
+    fn encrypt_last(self, payload: Payload) -> Result<Vec<u8>, String> {
+        match self {
+            Encryptor::Aes256Gcm(encryptor) => encryptor.encrypt_last(payload),
+            Encryptor::XChaCha20Poly1305(encryptor) => encryptor.encrypt_last(payload),
+        }
+        .map_err(|e| format!("Encryption failed: {}", e))
+    }
+}
+
+/// STREAM decryptor of the cipher of the file
+enum Decryptor {
+    Aes256Gcm(DecryptorBE32<Aes256Gcm>),
+    XChaCha20Poly1305(DecryptorBE32<XChaCha20Poly1305>),
+}
+
+impl Decryptor {
+    fn new(cipher: Cipher, key: &[u8; 32], nonce: &[u8]) -> Result<Self, String> {
+        Ok(match cipher {
+            Cipher::Aes256Gcm => Decryptor::Aes256Gcm(DecryptorBE32::from_aead(
+                Aes256Gcm::new_from_slice(key)
+                    .map_err(|e| format!("Failed to create cipher: {}", e))?,
+                GenericArray::from_slice(nonce),
+            )),
+            Cipher::XChaCha20Poly1305 => Decryptor::XChaCha20Poly1305(DecryptorBE32::from_aead(
+                XChaCha20Poly1305::new_from_slice(key)
+                    .map_err(|e| format!("Failed to create cipher: {}", e))?,
+                GenericArray::from_slice(nonce),
+            )),
+        })
+    }
+
+    fn decrypt_next(&mut self, payload: Payload) -> Result<Vec<u8>, aes_gcm::Error> {
+        match self {
+            Decryptor::Aes256Gcm(decryptor) => decryptor.decrypt_next(payload),
+            Decryptor::XChaCha20Poly1305(decryptor) => decryptor.decrypt_next(payload),
+        }
+    }
+
+    fn decrypt_last(self, payload: Payload) -> Result<Vec<u8>, aes_gcm::Error> {
+        match self {
+            Decryptor::Aes256Gcm(decryptor) => decryptor.decrypt_last(payload),
+            Decryptor::XChaCha20Poly1305(decryptor) => decryptor.decrypt_last(payload),
+        }
+    }
+}
+
+/// Reads until the buffer is full or the end of the reader, returns the number of bytes read
+pub fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize, String> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(format!("Failed to read file: {}", e)),
+        }
+    }
+    Ok(filled)
+}
+
+/// Writer that encrypts what is written to it in chunks with the STREAM construction
+/// and writes nonce prefix + chunks to the inner writer.
+/// Every chunk authenticates the header of the file, its position and whether it is
+/// the last one, so chunks can't be reordered or dropped. `finish` must be called at the end.
+/// The buffered plaintext is wiped when the encryptor is dropped.
+pub struct StreamEncryptor<W: Write> {
+    writer: W,
+    encryptor: Option<Encryptor>,
+    header: Vec<u8>,
+    buffer: Zeroizing<Vec<u8>>,
+}
+
+impl<W: Write> StreamEncryptor<W> {
+    pub fn new(
+        cipher: Cipher,
+        key: &[u8; 32],
+        header: &[u8],
+        mut writer: W,
+    ) -> Result<Self, String> {
+        let mut nonce = vec![0u8; stream_nonce_len(cipher)];
+        rand::fill(&mut nonce[..]);
+        writer
+            .write_all(&nonce)
+            .map_err(|e| format!("Failed to write file: {}", e))?;
+
+        Ok(StreamEncryptor {
+            writer,
+            encryptor: Some(Encryptor::new(cipher, key, &nonce)?),
+            header: header.to_vec(),
+            buffer: Zeroizing::new(Vec::with_capacity(STREAM_CHUNK_LEN)),
+        })
+    }
+
+    /// Encrypts the buffered chunk, it can't be the last one
+    fn write_chunk(&mut self) -> Result<(), String> {
+        let encrypted_chunk = self
+            .encryptor
+            .as_mut()
+            .ok_or("Stream already finished")?
+            .encrypt_next(Payload {
+                msg: &self.buffer,
+                aad: &self.header,
+            })?;
+        self.buffer.clear();
+
+        self.writer
+            .write_all(&encrypted_chunk)
+            .map_err(|e| format!("Failed to write file: {}", e))
+    }
+
+    /// Encrypts the rest as the last chunk, it can be empty, and returns the inner writer
+    pub fn finish(mut self) -> Result<W, String> {
+        let encrypted_chunk = self
+            .encryptor
+            .take()
+            .ok_or("Stream already finished")?
+            .encrypt_last(Payload {
+                msg: &self.buffer,
+                aad: &self.header,
+            })?;
+
+        self.writer
+            .write_all(&encrypted_chunk)
+            .map_err(|e| format!("Failed to write file: {}", e))?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for StreamEncryptor<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        // A full chunk is only written once more data follows, the last one is written by finish
+        if self.buffer.len() == STREAM_CHUNK_LEN {
+            self.write_chunk().map_err(io::Error::other)?;
+        }
+
+        let len = data.len().min(STREAM_CHUNK_LEN - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reader that decrypts nonce prefix + chunks written by `StreamEncryptor`.
+/// Fails if a chunk was changed, moved, or if the file was truncated.
+/// Every decrypted chunk is wiped once it was read.
+pub struct StreamDecryptor<R: Read> {
+    reader: R,
+    decryptor: Option<Decryptor>,
+    header: Vec<u8>,
+    /// Next encrypted chunk, read ahead to know if the current one is the last
+    pending: Vec<u8>,
+    content: Zeroizing<Vec<u8>>,
+    position: usize,
+    bytes_read: u64,
+}
+
+impl<R: Read> StreamDecryptor<R> {
+    pub fn new(
+        cipher: Cipher,
+        key: &[u8; 32],
+        header: &[u8],
+        mut reader: R,
+    ) -> Result<Self, String> {
+        let mut nonce = vec![0u8; stream_nonce_len(cipher)];
+        if read_full(&mut reader, &mut nonce)? < nonce.len() {
+            return Err("File is too short".to_string());
+        }
+
+        let mut pending = vec![0u8; STREAM_CHUNK_LEN + TAG_LEN];
+        let pending_len = read_full(&mut reader, &mut pending)?;
+        pending.truncate(pending_len);
+
+        Ok(StreamDecryptor {
+            reader,
+            decryptor: Some(Decryptor::new(cipher, key, &nonce)?),
+            header: header.to_vec(),
+            pending,
+            content: Zeroizing::new(Vec::new()),
+            position: 0,
+            bytes_read: (nonce.len() + pending_len) as u64,
+        })
+    }
+
+    /// Number of encrypted bytes read so far
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Decrypts the next chunk, returns None after the last one
+    pub fn next_chunk(&mut self) -> Result<Option<Zeroizing<Vec<u8>>>, String> {
+        if self.decryptor.is_none() {
+            return Ok(None);
+        }
+
+        // A full chunk is only the last one if nothing follows it
+        let chunk = std::mem::take(&mut self.pending);
+        if chunk.len() == STREAM_CHUNK_LEN + TAG_LEN {
+            self.pending = vec![0u8; STREAM_CHUNK_LEN + TAG_LEN];
+            let pending_len = read_full(&mut self.reader, &mut self.pending)?;
+            self.pending.truncate(pending_len);
+            self.bytes_read += pending_len as u64;
+        }
+        let payload = Payload {
+            msg: &chunk,
+            aad: &self.header,
+        };
+
+        // A truncated file ends with a chunk that wasn't written as the last one
+        let content = match self.pending.is_empty() {
+            true => self.decryptor.take().unwrap().decrypt_last(payload),
+            false => self.decryptor.as_mut().unwrap().decrypt_next(payload),
+        }
+        .map_err(|_| CHUNK_ERROR.to_string())?;
+
+        Ok(Some(Zeroizing::new(content)))
+    }
+}
+
+impl<R: Read> Read for StreamDecryptor<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.content.len() {
+            match self.next_chunk().map_err(io::Error::other)? {
+                Some(content) => {
+                    self.content = content;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let len = buffer.len().min(self.content.len() - self.position);
+        buffer[..len].copy_from_slice(&self.content[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Copies the reader into the writer a chunk at a time.
+/// The progress is called with the number of bytes read so far.
+pub fn copy_stream(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    mut progress: impl FnMut(u64),
+) -> Result<(), String> {
+    let mut chunk = Zeroizing::new(vec![0u8; STREAM_CHUNK_LEN]);
+    let mut done = 0u64;
+
+    loop {
+        let len = read_full(reader, &mut chunk)?;
+        if len == 0 {
+            return Ok(());
+        }
+        writer
+            .write_all(&chunk[..len])
+            .map_err(|e| format!("Failed to write file: {}", e))?;
+
+        done += len as u64;
+        progress(done);
+    }
+}
+
+/// Encrypts the reader in chunks and writes nonce prefix + chunks.
+/// The progress is called with the number of bytes read so far.
+pub fn encrypt_stream(
+    cipher: Cipher,
+    key: &[u8; 32],
+    header: &[u8],
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    progress: impl FnMut(u64),
+) -> Result<(), String> {
+    let mut encryptor = StreamEncryptor::new(cipher, key, header, writer)?;
+    copy_stream(reader, &mut encryptor, progress)?;
+    encryptor.finish().map(|_| ())
+}
+
+/// Decrypts nonce prefix + chunks into the writer.
+/// The progress is called with the number of bytes read so far.
+pub fn decrypt_stream(
+    cipher: Cipher,
+    key: &[u8; 32],
+    header: &[u8],
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    mut progress: impl FnMut(u64),
+) -> Result<(), String> {
+    let mut decryptor = StreamDecryptor::new(cipher, key, header, reader)?;
+
+    while let Some(content) = decryptor.next_chunk()? {
+        writer
+            .write_all(&content)
+            .map_err(|e| format!("Failed to write file: {}", e))?;
+        progress(decryptor.bytes_read());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [3; 32];
+    const HEADER: &[u8] = b"LCKD header";
+
+    fn encrypt(cipher: Cipher, content: &[u8]) -> Vec<u8> {
+        let mut encrypted = Vec::new();
+        encrypt_stream(
+            cipher,
+            &KEY,
+            HEADER,
+            &mut &content[..],
+            &mut encrypted,
+            |_| {},
+        )
+        .unwrap();
+        encrypted
+    }
+
+    fn decrypt(
+        cipher: Cipher,
+        key: &[u8; 32],
+        header: &[u8],
+        data: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        let mut content = Vec::new();
+        decrypt_stream(cipher, key, header, &mut &data[..], &mut content, |_| {})?;
+        Ok(content)
+    }
+
+    #[test]
+    fn round_trip() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+            for len in [
+                0,
+                1,
+                STREAM_CHUNK_LEN,
+                STREAM_CHUNK_LEN + 1,
+                2 * STREAM_CHUNK_LEN + 7,
+            ] {
+                let content: Vec<u8> = (0..len).map(|i| i as u8).collect();
+                let encrypted = encrypt(cipher, &content);
+
+                let chunks = len.div_ceil(STREAM_CHUNK_LEN).max(1);
+                assert_eq!(
+                    encrypted.len(),
+                    stream_nonce_len(cipher) + len + chunks * TAG_LEN
+                );
+                assert_eq!(decrypt(cipher, &KEY, HEADER, &encrypted).unwrap(), content);
+            }
+        }
+    }
+
+    #[test]
+    fn reader_round_trip() {
+        let content = vec![5u8; STREAM_CHUNK_LEN + 100];
+        let encrypted = encrypt(Cipher::XChaCha20Poly1305, &content);
+
+        let mut decryptor =
+            StreamDecryptor::new(Cipher::XChaCha20Poly1305, &KEY, HEADER, &encrypted[..]).unwrap();
+        let mut decrypted = Vec::new();
+        decryptor.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, content);
+    }
+
+    #[test]
+    fn modified_chunk_fails() {
+        let mut encrypted = encrypt(Cipher::Aes256Gcm, b"secret note");
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+
+        assert_eq!(
+            decrypt(Cipher::Aes256Gcm, &KEY, HEADER, &encrypted).unwrap_err(),
+            CHUNK_ERROR
+        );
+    }
+
+    #[test]
+    fn wrong_key_or_header_fails() {
+        let encrypted = encrypt(Cipher::Aes256Gcm, b"secret note");
+
+        assert!(decrypt(Cipher::Aes256Gcm, &[4; 32], HEADER, &encrypted).is_err());
+        assert!(decrypt(Cipher::Aes256Gcm, &KEY, b"other header", &encrypted).is_err());
+    }
+
+    #[test]
+    fn truncated_stream_fails() {
+        let content = vec![1u8; 2 * STREAM_CHUNK_LEN + 10];
+        let encrypted = encrypt(Cipher::XChaCha20Poly1305, &content);
+
+        // Without the last chunk the one before it wasn't written as the last one
+        let full_chunks_end =
+            stream_nonce_len(Cipher::XChaCha20Poly1305) + 2 * (STREAM_CHUNK_LEN + TAG_LEN);
+        assert!(decrypt(
+            Cipher::XChaCha20Poly1305,
+            &KEY,
+            HEADER,
+            &encrypted[..full_chunks_end]
+        )
+        .is_err());
+
+        // Without the nonce prefix there is nothing to decrypt
+        assert!(decrypt(Cipher::XChaCha20Poly1305, &KEY, HEADER, &encrypted[..3]).is_err());
+    }
+
+    #[test]
+    fn swapped_chunks_fail() {
+        let content: Vec<u8> = (0..2 * STREAM_CHUNK_LEN + 10).map(|i| i as u8).collect();
+        let mut encrypted = encrypt(Cipher::Aes256Gcm, &content);
+
+        let start = stream_nonce_len(Cipher::Aes256Gcm);
+        let chunk_len = STREAM_CHUNK_LEN + TAG_LEN;
+        let (first, rest) = encrypted[start..].split_at_mut(chunk_len);
+        first.swap_with_slice(&mut rest[..chunk_len]);
+
+        assert!(decrypt(Cipher::Aes256Gcm, &KEY, HEADER, &encrypted).is_err());
+    }
+}
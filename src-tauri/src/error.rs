@@ -0,0 +1,72 @@
+use crate::encryption::TAMPER_ERROR;
+use std::fmt;
+
+/// Error of the commands whose failures the frontend handles differently.
+/// It is serialized as { kind, message, ... } so the frontend can check the kind.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum CommandError {
+    /// The note was changed on disk since it was opened or saved
+    Conflict { id: String, message: String },
+    /// An item with the same name is already in the folder, the suggestion is a free name
+    Exists {
+        name: String,
+        suggestion: String,
+        message: String,
+    },
+    /// The note doesn't match its location in the vault, the user can accept it there
+    Tampered { id: String, message: String },
+    /// Any other error, it is only shown to the user
+    Other { message: String },
+}
+
+impl CommandError {
+    pub fn conflict(id: &str) -> Self {
+        CommandError::Conflict {
+            id: id.to_string(),
+            message: "The note was changed outside of the app since it was opened".to_string(),
+        }
+    }
+
+    pub fn exists(name: &str, suggestion: &str) -> Self {
+        CommandError::Exists {
+            name: name.to_string(),
+            suggestion: suggestion.to_string(),
+            message: format!("'{}' already exists in this folder", name),
+        }
+    }
+
+    pub fn tampered(id: &str) -> Self {
+        CommandError::Tampered {
+            id: id.to_string(),
+            message: TAMPER_ERROR.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Conflict { message, .. }
+            | CommandError::Exists { message, .. }
+            | CommandError::Tampered { message, .. }
+            | CommandError::Other { message } => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::Other { message }
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> Self {
+        CommandError::Other {
+            message: message.to_string(),
+        }
+    }
+}
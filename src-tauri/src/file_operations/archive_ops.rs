@@ -0,0 +1,508 @@
+use crate::encryption::{keyring::Keyring, stream_decryptor, stream_encryptor};
+use crate::file_operations::{
+    fs_utils::write_atomic_with,
+    job_ops::{FolderJobKind, Job},
+};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tauri::AppHandle;
+
+/// Extension of the encrypted archives of folders
+pub const ARCHIVE_EXTENSION: &str = "lockdarc";
+
+/// Magic bytes at the start of the decrypted content of an archive
+const ARCHIVE_MAGIC: [u8; 4] = *b"LKAR";
+
+/// Current version of the archive format
+const ARCHIVE_VERSION: u8 = 1;
+
+/// The entries are stored as they are
+const COMPRESSION_NONE: u8 = 0;
+/// The entries are compressed with deflate
+const COMPRESSION_DEFLATE: u8 = 1;
+
+/// Kinds of the entries of an archive, the last entry is ENTRY_END
+const ENTRY_END: u8 = 0;
+const ENTRY_DIR: u8 = 1;
+const ENTRY_FILE: u8 = 2;
+
+/// Item of the folder to pack, with its path inside the archive
+struct ArchiveItem {
+    path: PathBuf,
+    name: String,
+    is_dir: bool,
+}
+
+/// Lists the items of a folder with their path relative to the root, folders first.
+/// Folders that can't be read and names that aren't valid UTF-8 are reported and skipped.
+fn collect_archive_items(root: &Path, dir: &Path, items: &mut Vec<ArchiveItem>, job: &mut Job) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            job.fail(dir, format!("Failed to read directory: {}", e));
+            return;
+        }
+    };
+
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                job.fail(dir, format!("Failed to read directory entry: {}", e));
+                continue;
+            }
+        };
+
+        // The names inside the archive always use / as separator
+        let name = path
+            .strip_prefix(root)
+            .ok()
+            .and_then(|relative_path| {
+                relative_path
+                    .components()
+                    .map(|part| part.as_os_str().to_str())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .map(|parts| parts.join("/"));
+        let Some(name) = name else {
+            job.fail(&path, "The name is not valid UTF-8".to_string());
+            continue;
+        };
+
+        if path.is_dir() {
+            items.push(ArchiveItem {
+                path: path.clone(),
+                name,
+                is_dir: true,
+            });
+            collect_archive_items(root, &path, items, job);
+        } else if path.is_file() {
+            items.push(ArchiveItem {
+                path,
+                name,
+                is_dir: false,
+            });
+        }
+    }
+}
+
+/// Writes the start of the decrypted content of an archive, before the entries
+fn write_archive_header(
+    writer: &mut impl Write,
+    compression: u8,
+    file_count: usize,
+) -> Result<(), String> {
+    let file_count = u32::try_from(file_count)
+        .map_err(|_| format!("Too many files to archive: {}", file_count))?;
+
+    writer
+        .write_all(&ARCHIVE_MAGIC)
+        .and_then(|_| writer.write_all(&[ARCHIVE_VERSION, compression]))
+        .and_then(|_| writer.write_all(&file_count.to_le_bytes()))
+        .map_err(|e| format!("Failed to write archive: {}", e))
+}
+
+/// Reads the start of the decrypted content of an archive,
+/// returns the compression of the entries and the number of files
+fn read_archive_header(reader: &mut impl Read) -> Result<(u8, usize), String> {
+    let [magic @ .., version, compression] = read_bytes::<6>(reader)?;
+    if magic != ARCHIVE_MAGIC {
+        return Err("The file is not an archive".to_string());
+    }
+    if version == 0 || version > ARCHIVE_VERSION {
+        return Err(format!(
+            "Unsupported archive version {}, try updating the app",
+            version
+        ));
+    }
+    if compression != COMPRESSION_NONE && compression != COMPRESSION_DEFLATE {
+        return Err(format!(
+            "Unsupported archive compression (id {})",
+            compression
+        ));
+    }
+
+    let file_count = u32::from_le_bytes(read_bytes(reader)?) as usize;
+    Ok((compression, file_count))
+}
+
+/// Writes the kind and the path of an entry (kind (1) | path length (2) | path)
+fn write_entry_header(writer: &mut impl Write, kind: u8, name: &str) -> Result<(), String> {
+    let name_len = u16::try_from(name.len()).map_err(|_| format!("'{}' is too long", name))?;
+
+    writer
+        .write_all(&[kind])
+        .and_then(|_| writer.write_all(&name_len.to_le_bytes()))
+        .and_then(|_| writer.write_all(name.as_bytes()))
+        .map_err(|e| format!("Failed to write archive: {}", e))
+}
+
+/// Writes the entries of the items, the last one is ENTRY_END
+fn write_entries(
+    writer: &mut impl Write,
+    items: &[ArchiveItem],
+    job: &mut Job,
+) -> Result<(), String> {
+    for item in items {
+        if job.is_cancelled() {
+            return Err("The archive was cancelled".to_string());
+        }
+
+        if item.is_dir {
+            write_entry_header(writer, ENTRY_DIR, &item.name)?;
+            continue;
+        }
+
+        match File::open(&item.path).and_then(|source| Ok((source.metadata()?.len(), source))) {
+            Ok((size, source)) => {
+                write_entry_header(writer, ENTRY_FILE, &item.name)?;
+                writer
+                    .write_all(&size.to_le_bytes())
+                    .map_err(|e| format!("Failed to write archive: {}", e))?;
+
+                // The size is written first, so the file must not change meanwhile
+                let copied = std::io::copy(&mut source.take(size), writer)
+                    .map_err(|e| format!("Failed to archive {}: {}", item.name, e))?;
+                if copied != size {
+                    return Err(format!("{} changed while it was archived", item.name));
+                }
+            }
+            Err(e) => job.fail(&item.path, format!("Failed to read file: {}", e)),
+        }
+        job.file_done(&item.path);
+    }
+
+    writer
+        .write_all(&[ENTRY_END])
+        .map_err(|e| format!("Failed to write archive: {}", e))
+}
+
+/// Packs a folder into a single encrypted archive, so the names, number and sizes
+/// of its files are hidden. The decrypted content of the archive is:
+///
+/// magic (4) | version (1) | compression (1) | number of files (4) | entries
+///
+/// and the entries, compressed if set, are kind (1) | path length (2) | path |
+/// size (8) | content for files. Integers are little endian, the last entry is ENTRY_END.
+/// The entries are compressed if it is set in the encryption options.
+/// Files that can't be read are reported and skipped, the archive is only written
+/// when it is complete.
+pub fn pack_folder(
+    folder_path: &Path,
+    archive_path: &Path,
+    key: &Keyring,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
+    let mut job = Job::new(
+        FolderJobKind::Archive,
+        folder_path,
+        archive_path,
+        app_handle,
+    )?;
+
+    let mut items = Vec::new();
+    collect_archive_items(folder_path, folder_path, &mut items, &mut job);
+    let file_count = items.iter().filter(|item| !item.is_dir).count();
+    job.start(file_count);
+
+    let compression = match key.options().compress {
+        true => COMPRESSION_DEFLATE,
+        false => COMPRESSION_NONE,
+    };
+
+    let result = write_atomic_with(archive_path, |file| {
+        let mut writer = stream_encryptor(key, archive_path, BufWriter::new(file))?;
+        write_archive_header(&mut writer, compression, file_count)?;
+
+        if compression == COMPRESSION_DEFLATE {
+            let mut encoder = DeflateEncoder::new(&mut writer, flate2::Compression::default());
+            write_entries(&mut encoder, &items, &mut job)?;
+            encoder
+                .finish()
+                .map_err(|e| format!("Failed to write archive: {}", e))?;
+        } else {
+            write_entries(&mut writer, &items, &mut job)?;
+        }
+
+        writer
+            .finish()?
+            .flush()
+            .map_err(|e| format!("Failed to write archive: {}", e))
+    });
+
+    // A cancelled archive is not written at all
+    if let Err(err) = result {
+        if !job.is_cancelled() {
+            job.fail(folder_path, err);
+        }
+    }
+
+    job.finish()
+}
+
+/// Reads the next N bytes of the archive
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], String> {
+    let mut bytes = [0u8; N];
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|e| format!("Failed to read archive: {}", e))?;
+    Ok(bytes)
+}
+
+/// Reads the path of an entry, after its kind
+fn read_entry_name(reader: &mut impl Read) -> Result<String, String> {
+    let name_len = u16::from_le_bytes(read_bytes(reader)?) as usize;
+    let mut name = vec![0u8; name_len];
+    reader
+        .read_exact(&mut name)
+        .map_err(|e| format!("Failed to read archive: {}", e))?;
+    String::from_utf8(name).map_err(|_| "Invalid path in archive".to_string())
+}
+
+/// Rebuilds the path of an entry inside the output folder,
+/// so an archive can't write outside of it
+fn entry_path(output_folder: &Path, name: &str) -> Result<PathBuf, String> {
+    let mut path = output_folder.to_path_buf();
+    for part in name.split('/') {
+        let is_normal = matches!(
+            Path::new(part).components().collect::<Vec<_>>().as_slice(),
+            [Component::Normal(normal)] if *normal == part
+        );
+        if !is_normal || part.contains(['\\', ':']) {
+            return Err(format!("Invalid path in archive: {}", name));
+        }
+        path.push(part);
+    }
+    Ok(path)
+}
+
+/// Restores the folder of an encrypted archive into the output folder.
+/// Files that can't be written are reported and skipped, but the job stops
+/// if the archive was modified, only the files before the change are restored.
+/// A cancelled job removes the files and folders it created, the files it replaced are kept.
+pub fn extract_archive(
+    archive_path: &Path,
+    output_folder: &Path,
+    key: &Keyring,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
+    let mut job = Job::new(
+        FolderJobKind::Extract,
+        archive_path,
+        output_folder,
+        app_handle,
+    )?;
+
+    let mut created = Vec::new();
+    let result = extract_entries(archive_path, output_folder, key, &mut job, &mut created);
+
+    if job.is_cancelled() {
+        remove_created(&created);
+    } else if let Err(err) = result {
+        job.fail(archive_path, err);
+    }
+
+    job.finish()
+}
+
+/// Creates a folder and its missing parents, adding the ones that didn't exist to the list
+fn create_dirs(path: &Path, created: &mut Vec<PathBuf>) -> Result<(), String> {
+    let missing: Vec<PathBuf> = path
+        .ancestors()
+        .take_while(|dir| !dir.exists())
+        .map(Path::to_path_buf)
+        .collect();
+
+    std::fs::create_dir_all(path).map_err(|e| format!("Failed to create directory: {}", e))?;
+    created.extend(missing.into_iter().rev());
+    Ok(())
+}
+
+/// Removes the files and folders created by an extraction, the newest first.
+/// Folders are only removed if they are empty, something else could have been put there.
+fn remove_created(created: &[PathBuf]) {
+    for path in created.iter().rev() {
+        let _ = match path.is_dir() {
+            true => std::fs::remove_dir(path),
+            false => std::fs::remove_file(path),
+        };
+    }
+}
+
+/// Reads the entries of an archive and writes them to the output folder.
+/// Adds the files and folders it creates to the list, in the order they are created.
+fn extract_entries(
+    archive_path: &Path,
+    output_folder: &Path,
+    key: &Keyring,
+    job: &mut Job,
+    created: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let file = File::open(archive_path).map_err(|e| format!("Failed to read archive: {}", e))?;
+    let mut reader = stream_decryptor(key, archive_path, BufReader::new(file))?;
+
+    let (compression, file_count) = read_archive_header(&mut reader)?;
+    job.start(file_count);
+
+    let mut reader: Box<dyn Read> = match compression {
+        COMPRESSION_DEFLATE => Box::new(DeflateDecoder::new(reader)),
+        _ => Box::new(reader),
+    };
+
+    create_dirs(output_folder, created)?;
+
+    loop {
+        let [kind] = read_bytes(&mut reader)?;
+        if kind == ENTRY_END {
+            return Ok(());
+        }
+        if job.is_cancelled() {
+            return Ok(());
+        }
+
+        let name = read_entry_name(&mut reader)?;
+        let path = entry_path(output_folder, &name)?;
+
+        match kind {
+            ENTRY_DIR => {
+                if let Err(err) = create_dirs(&path, created) {
+                    job.fail(&path, err);
+                }
+            }
+            ENTRY_FILE => {
+                let size = u64::from_le_bytes(read_bytes(&mut reader)?);
+                let mut content = (&mut reader).take(size);
+                let existed = path.exists();
+
+                let result = match path.parent() {
+                    Some(parent) => create_dirs(parent, created),
+                    None => Ok(()),
+                }
+                .and_then(|_| {
+                    write_atomic_with(&path, |output| {
+                        let copied = std::io::copy(&mut content, output)
+                            .map_err(|e| format!("Failed to extract {}: {}", name, e))?;
+                        match copied == size {
+                            true => Ok(()),
+                            false => Err("The archive is truncated".to_string()),
+                        }
+                    })
+                });
+
+                if result.is_ok() && !existed {
+                    created.push(path.clone());
+                }
+
+                // Skip the rest of the file, the archive itself must still be valid
+                if let Err(err) = result {
+                    std::io::copy(&mut content, &mut std::io::sink())
+                        .map_err(|e| format!("Failed to read archive: {}", e))?;
+                    if content.limit() > 0 {
+                        return Err(err);
+                    }
+                    job.fail(&path, err);
+                }
+                job.file_done(&path);
+            }
+            _ => return Err(format!("Unsupported archive entry (id {})", kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_header_round_trip() {
+        for compression in [COMPRESSION_NONE, COMPRESSION_DEFLATE] {
+            let mut data = Vec::new();
+            write_archive_header(&mut data, compression, 42).unwrap();
+
+            assert_eq!(
+                read_archive_header(&mut &data[..]).unwrap(),
+                (compression, 42)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_archive_header_is_rejected() {
+        let mut data = Vec::new();
+        write_archive_header(&mut data, COMPRESSION_NONE, 1).unwrap();
+
+        let mut magic = data.clone();
+        magic[0] ^= 1;
+        assert!(read_archive_header(&mut &magic[..]).is_err());
+
+        let mut version = data.clone();
+        version[4] = ARCHIVE_VERSION + 1;
+        assert!(read_archive_header(&mut &version[..]).is_err());
+
+        let mut compression = data.clone();
+        compression[5] = 0xff;
+        assert!(read_archive_header(&mut &compression[..]).is_err());
+
+        assert!(read_archive_header(&mut &data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn too_many_files_are_rejected() {
+        let file_count = u32::MAX as usize + 1;
+        assert!(write_archive_header(&mut Vec::new(), COMPRESSION_NONE, file_count).is_err());
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let mut data = Vec::new();
+        write_entry_header(&mut data, ENTRY_FILE, "notes/día 1.lockd").unwrap();
+        write_entry_header(&mut data, ENTRY_DIR, "notes").unwrap();
+
+        let mut reader = &data[..];
+        assert_eq!(read_bytes::<1>(&mut reader).unwrap(), [ENTRY_FILE]);
+        assert_eq!(read_entry_name(&mut reader).unwrap(), "notes/día 1.lockd");
+        assert_eq!(read_bytes::<1>(&mut reader).unwrap(), [ENTRY_DIR]);
+        assert_eq!(read_entry_name(&mut reader).unwrap(), "notes");
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn invalid_entry_name_is_rejected() {
+        let mut data = 2u16.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0xff, 0xfe]);
+        assert!(read_entry_name(&mut &data[..]).is_err());
+
+        // The name is shorter than its length
+        let mut data = 10u16.to_le_bytes().to_vec();
+        data.extend_from_slice(b"abc");
+        assert!(read_entry_name(&mut &data[..]).is_err());
+
+        assert!(write_entry_header(&mut Vec::new(), ENTRY_FILE, &"x".repeat(70_000)).is_err());
+    }
+
+    #[test]
+    fn entry_paths_stay_inside_the_output_folder() {
+        let output = Path::new("output");
+        assert_eq!(
+            entry_path(output, "a/b.lockd").unwrap(),
+            output.join("a").join("b.lockd")
+        );
+
+        for name in [
+            "",
+            ".",
+            "..",
+            "../x",
+            "a/../../x",
+            "/x",
+            "a//b",
+            "a\\b",
+            "C:x",
+            "a/.",
+        ] {
+            assert!(entry_path(output, name).is_err(), "{}", name);
+        }
+    }
+}
@@ -1,10 +1,23 @@
-use crate::encryption::encrypt_data;
-use crate::file_operations::note_ops::{open_note_and_emit, open_note_from_path};
+use crate::encryption::{
+    decrypt_data_unchecked, encrypt_data, options::EncryptionOptions, TAMPER_ERROR,
+};
+use crate::error::CommandError;
+use crate::file_operations::fs_utils::{write_atomic, FileStamp};
+use crate::file_operations::history_ops::HistoryRetention;
+use crate::file_operations::item_ops::{
+    check_free_path, finish_relocation, relocate_item, validate_note_title,
+};
+use crate::file_operations::name_ops::item_path;
+use crate::file_operations::note_ops::{
+    open_note_and_emit, open_note_from_path, relocate_note_data, write_note,
+};
+use crate::lock_ops::idle_timeout_from_config;
 use crate::state::{AppState, FileSystemItemFrontend};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use tauri::{AppHandle, Emitter, Manager, State, Window};
 use tauri_plugin_dialog::DialogExt;
+use zeroize::Zeroizing;
 
 /// Tauri command to get opened items
 #[tauri::command]
@@ -22,7 +35,16 @@ pub fn close_item(
     app_state: State<Mutex<AppState>>,
     window: Window,
 ) -> Result<(), String> {
-    app_state.lock().unwrap().remove_opened_item(&id);
+    {
+        let mut state = app_state.lock().unwrap();
+
+        // Forget the data key of a closed vault
+        if let (Some(path), Ok(key)) = (state.get_path_from_id(&id), state.get_encryption_key()) {
+            key.remove_vault(Path::new(&path));
+        }
+
+        state.remove_opened_item(&id);
+    }
 
     // Emit event to frontend
     window
@@ -39,7 +61,7 @@ pub fn open_note_from_id(
     parent_id: &str,
     app_state: State<Mutex<AppState>>,
     window: Window,
-) -> Result<(), String> {
+) -> Result<(), CommandError> {
     // Get the actual file path from the ID
     let note_path = {
         let state = app_state.lock().unwrap();
@@ -54,33 +76,60 @@ pub fn open_note_from_id(
         &window,
         &app_state,
     )
+    .map_err(|e| match e.as_str() {
+        TAMPER_ERROR => CommandError::tampered(id),
+        _ => CommandError::from(e),
+    })
 }
 
+/// Tauri command to open a note that was moved or renamed outside of the app.
+/// It is encrypted again so it is tagged with its current location.
 #[tauri::command]
-/// Encrypts a note and saves it to a given path.
-pub fn save_note(id: &str, content: &str, app_state: State<Mutex<AppState>>) -> Result<(), String> {
-    // Get the encryption key
-    let key = app_state
+pub fn accept_note_location(
+    id: &str,
+    parent_id: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<(), String> {
+    let note_path = app_state
         .lock()
         .unwrap()
-        .get_encryption_key()
-        .map_err(|e| e.to_string())?;
-
-    // Create the path if it doesn't exist
-    let file_path = app_state.lock().unwrap().get_path_from_id(id).unwrap();
-    let path_buf = PathBuf::from(file_path);
-    if let Some(parent) = path_buf.parent() {
-        std::fs::create_dir_all(parent)
-            .map_err(|e| format!("Failed to create directory: {}", e))?;
-    }
+        .get_path_from_id(id)
+        .ok_or("Note not found")?;
+    let path = PathBuf::from(&note_path);
 
-    // Encrypt the content
-    let file_data = encrypt_data(&key, content.as_bytes()).map_err(|e| e.to_string())?;
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+    let file_data = std::fs::read(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let content = Zeroizing::new(decrypt_data_unchecked(&key, &file_data)?);
+    let file_data = encrypt_data(&key, &path, &content)?;
 
-    // Write the encrypted data to the file
-    std::fs::write(path_buf, file_data).map_err(|e| format!("Failed to write file: {}", e))?;
+    // The watcher must not report the new content as an outside change
+    app_state
+        .lock()
+        .unwrap()
+        .set_file_stamp(note_path, FileStamp::from_data(&file_data));
+    write_atomic(&path, &file_data)?;
 
-    Ok(())
+    open_note_and_emit(
+        id.to_string(),
+        parent_id.to_string(),
+        &path,
+        &window,
+        &app_state,
+    )
+}
+
+#[tauri::command]
+/// Encrypts a note and saves it to a given path.
+/// The previous version is kept in the history of the vault.
+/// Fails with a conflict if the file was changed outside of the app since it was opened.
+pub fn save_note(
+    id: &str,
+    content: &str,
+    history: Option<HistoryRetention>,
+    app_state: State<Mutex<AppState>>,
+) -> Result<(), CommandError> {
+    write_note(id, content, history.unwrap_or_default(), false, &app_state)
 }
 
 #[tauri::command]
@@ -119,10 +168,9 @@ pub fn save_note_as(
     // If the user selected a file, write the encrypted data to it
     if let Some(path) = file_path {
         // Encrypt the content
-        let file_data = encrypt_data(&key, content.as_bytes())?;
+        let file_data = encrypt_data(&key, path.as_path().unwrap(), content.as_bytes())?;
 
-        std::fs::write(path.as_path().unwrap(), file_data)
-            .map_err(|e| format!("Failed to write file: {}", e))?;
+        write_atomic(path.as_path().unwrap(), &file_data)?;
 
         // Immediately open the note from the path to add it to the opened items
         // and emit the event to the frontend
@@ -169,10 +217,9 @@ pub fn save_note_copy(
     // It is Ok to cancel the dialog
     if let Some(path) = file_path {
         // Encrypt the content
-        let file_data = encrypt_data(&key, content.as_bytes())?;
+        let file_data = encrypt_data(&key, path.as_path().unwrap(), content.as_bytes())?;
 
-        std::fs::write(path.as_path().unwrap(), file_data)
-            .map_err(|e| format!("Failed to write file: {}", e))?;
+        write_atomic(path.as_path().unwrap(), &file_data)?;
     } else {
         return Ok(false);
     }
@@ -187,29 +234,51 @@ pub fn rename_note(
     new_title: &str,
     app_state: State<Mutex<AppState>>,
     window: Window,
-) -> Result<(), String> {
-    let mut state = app_state.lock().unwrap();
-    let note_path = state.get_path_from_id(id).ok_or("Note not found")?;
+) -> Result<(), CommandError> {
+    let new_title = new_title.trim();
+    validate_note_title(new_title)?;
 
-    // Get the parent directory of the note
-    let binding = PathBuf::from(&note_path);
-    let parent_dir = binding.parent().ok_or("Invalid note path")?;
+    let mut state = app_state.lock().unwrap();
+    let note_path = PathBuf::from(state.get_path_from_id(id).ok_or("Note not found")?);
 
-    // Create the new file name with the new title
+    // Create the new file name with the new title, it can be encrypted on disk
+    let key = state.get_encryption_key()?;
+    let parent_dir = note_path.parent().ok_or("Invalid note path")?;
     let new_file_name = format!("{}.lockd", new_title);
-    let new_file_path = parent_dir.join(&new_file_name);
-
-    // Rename the file
-    std::fs::rename(note_path, &new_file_path)
-        .map_err(|e| format!("Failed to rename note: {}", e))?;
+    let new_file_path = item_path(&key, parent_dir, &new_file_name)?;
+    if new_file_path == note_path {
+        return Ok(());
+    }
+    check_free_path(&key, &new_file_path, &note_path)?;
+
+    // Notes inside an opened folder are moved in its tree
+    let relocation = match state.find_root_of(id).map(|root| root.id.clone()) {
+        Some(root_id) if root_id != id => Some(relocate_item(
+            &mut state,
+            &root_id,
+            &note_path,
+            &new_file_path,
+        )?),
+        _ => {
+            std::fs::rename(&note_path, &new_file_path)
+                .map_err(|e| format!("Failed to rename note: {}", e))?;
+
+            // Update the state with the new path
+            state.update_note_path(
+                id,
+                parent_id,
+                new_file_path.to_string_lossy().to_string(),
+                new_file_name.clone(),
+            );
+            relocate_note_data(&note_path, &new_file_path, &app_state)?;
+            None
+        }
+    };
+    drop(state);
 
-    // Update the state with the new path
-    state.update_note_path(
-        id,
-        parent_id,
-        new_file_path.to_string_lossy().to_string(),
-        new_file_name.clone(),
-    );
+    if let Some((moved, notes)) = relocation {
+        finish_relocation(moved, notes, &window)?;
+    }
 
     window
         .emit("note-renamed", (id, parent_id, new_file_name))
@@ -218,9 +287,8 @@ pub fn rename_note(
     Ok(())
 }
 
-#[tauri::command]
-/// Returns the contents of the default configuration file.
-pub fn get_initial_config(app_handle: AppHandle) -> Result<String, String> {
+/// Returns the path of a file in the app config directory, creating the directory
+pub fn config_dir_file(app_handle: &AppHandle, name: &str) -> Result<PathBuf, String> {
     let app_dir = app_handle
         .path()
         .app_config_dir()
@@ -230,7 +298,17 @@ pub fn get_initial_config(app_handle: AppHandle) -> Result<String, String> {
     std::fs::create_dir_all(&app_dir)
         .map_err(|e| format!("Failed to create app config directory: {}", e))?;
 
-    let config_path = app_dir.join(".lockdfg");
+    Ok(app_dir.join(name))
+}
+
+/// Returns the path of the default configuration file, creating its directory
+fn config_path(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    config_dir_file(app_handle, ".lockdfg")
+}
+
+/// Reads the default configuration file, it is created empty if it doesn't exist
+pub fn read_config(app_handle: &AppHandle) -> Result<String, String> {
+    let config_path = config_path(app_handle)?;
 
     // Create the config file with empty content if it doesn't exist
     if !config_path.exists() {
@@ -243,22 +321,27 @@ pub fn get_initial_config(app_handle: AppHandle) -> Result<String, String> {
 }
 
 #[tauri::command]
-/// Saves the default configuration file with the provided content.
-pub fn save_initial_config(content: &str, app_handle: AppHandle) -> Result<(), String> {
-    let app_dir = app_handle
-        .path()
-        .app_config_dir()
-        .map_err(|_| "Failed to get app config base directory")?;
-
-    // Always create the app config directory, even if it already exists
-    std::fs::create_dir_all(&app_dir)
-        .map_err(|e| format!("Failed to create app config directory: {}", e))?;
-
-    let config_path = app_dir.join(".lockdfg");
+/// Returns the contents of the default configuration file.
+pub fn get_initial_config(app_handle: AppHandle) -> Result<String, String> {
+    read_config(&app_handle)
+}
 
+#[tauri::command]
+/// Saves the default configuration file with the provided content.
+/// The encryption options apply to the files written from now on.
+pub fn save_initial_config(
+    content: &str,
+    app_state: State<Mutex<AppState>>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
     // Write the provided content to the config file
-    std::fs::write(&config_path, content)
-        .map_err(|e| format!("Failed to write default config file: {}", e))?;
+    write_atomic(&config_path(&app_handle)?, content.as_bytes())?;
+
+    let mut state = app_state.lock().unwrap();
+    if let Ok(key) = state.get_encryption_key() {
+        key.set_options(EncryptionOptions::from_config(content));
+    }
+    state.set_idle_timeout(idle_timeout_from_config(content));
 
     Ok(())
 }
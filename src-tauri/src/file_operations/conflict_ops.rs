@@ -0,0 +1,93 @@
+use crate::encryption::encrypt_data;
+use crate::encryption::keyring::Keyring;
+use crate::error::CommandError;
+use crate::file_operations::{
+    fs_utils::write_atomic,
+    history_ops::HistoryRetention,
+    name_ops::{display_name, item_path},
+    note_ops::{open_note_and_emit, open_note_from_path, write_note},
+};
+use crate::state::AppState;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{State, Window};
+
+#[tauri::command]
+/// Saves a note over the changes made outside of the app
+pub fn overwrite_note(
+    id: &str,
+    content: &str,
+    history: Option<HistoryRetention>,
+    app_state: State<Mutex<AppState>>,
+) -> Result<(), CommandError> {
+    write_note(id, content, history.unwrap_or_default(), true, &app_state)
+}
+
+#[tauri::command]
+/// Discards the unsaved changes and opens the note again as it is on disk
+pub fn reload_note(
+    id: &str,
+    parent_id: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<(), String> {
+    let note_path = {
+        let state = app_state.lock().unwrap();
+        state.get_path_from_id(id).ok_or("Note not found")?
+    };
+
+    open_note_and_emit(
+        id.to_string(),
+        parent_id.to_string(),
+        &PathBuf::from(note_path),
+        &window,
+        &app_state,
+    )
+}
+
+#[tauri::command]
+/// Saves the content next to a note that was changed outside of the app,
+/// so neither version is lost, and opens the copy
+pub fn save_conflict_copy(
+    id: &str,
+    content: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<(), String> {
+    let (key, note_path) = {
+        let state = app_state.lock().unwrap();
+        let key = state.get_encryption_key()?;
+        let note_path = state.get_path_from_id(id).ok_or("Note not found")?;
+        (key, PathBuf::from(note_path))
+    };
+
+    let copy_path = conflict_copy_path(&key, &note_path)?;
+
+    let file_data = encrypt_data(&key, &copy_path, content.as_bytes())?;
+    write_atomic(&copy_path, &file_data)?;
+
+    open_note_from_path(&copy_path, &window, app_state)
+}
+
+/// Finds a free name for the conflict copy of a note, "name (conflict).lockd",
+/// "name (conflict 2).lockd"...
+fn conflict_copy_path(key: &Keyring, note_path: &Path) -> Result<PathBuf, String> {
+    let parent = note_path.parent().ok_or("Invalid note path")?;
+
+    // The name can be encrypted on disk
+    let name = display_name(key, note_path);
+    let title = name.strip_suffix(".lockd").unwrap_or(&name);
+
+    for n in 1..1000 {
+        let copy_name = match n {
+            1 => format!("{} (conflict).lockd", title),
+            n => format!("{} (conflict {}).lockd", title, n),
+        };
+        let path = item_path(key, parent, &copy_name)?;
+        if !path.exists() {
+            return Ok(path);
+        }
+    }
+
+    Err("Too many conflict copies of the note".to_string())
+}
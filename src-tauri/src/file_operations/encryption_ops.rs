@@ -1,9 +1,96 @@
-use crate::encryption::{decrypt_data, encrypt_data};
+use crate::encryption::{decrypt_stream_to, encrypt_stream_to, keyring::Keyring};
+use crate::file_operations::{
+    archive_ops::{extract_archive, pack_folder, ARCHIVE_EXTENSION},
+    fs_utils::write_atomic_with,
+    job_ops::{run_file_job, run_folder_job, FolderJobKind},
+};
 use crate::state::AppState;
-use std::path::PathBuf;
-use std::sync::Mutex;
-use tauri::{Manager, State, Window};
-use tauri_plugin_dialog::DialogExt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, Manager, State, Window};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons};
+
+/// Progress of the encryption or decryption of a file, there is an event for each chunk
+#[derive(Clone, serde::Serialize)]
+struct FileProgress {
+    done: u64,
+    total: u64,
+    path: String,
+}
+
+/// Reader of a file that fails once its job is cancelled, so a large file stops in the middle
+struct JobReader<'a> {
+    file: File,
+    cancelled: &'a AtomicBool,
+}
+
+impl Read for JobReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(std::io::Error::other("The job was cancelled"));
+        }
+        self.file.read(buf)
+    }
+}
+
+/// Opens a file to encrypt or decrypt and returns it with its size
+fn open_source<'a>(path: &Path, cancelled: &'a AtomicBool) -> Result<(JobReader<'a>, u64), String> {
+    let file =
+        File::open(path).map_err(|e| format!("Failed to read file {}: {}", path.display(), e))?;
+    let total = file.metadata().map(|m| m.len()).unwrap_or(0);
+    Ok((JobReader { file, cancelled }, total))
+}
+
+/// Emits the progress of a file, the events are only informative so failures are ignored
+fn emit_progress(app_handle: &AppHandle, path: &Path, done: u64, total: u64) {
+    let _ = app_handle.emit(
+        "file-progress",
+        FileProgress {
+            done,
+            total,
+            path: path.to_string_lossy().to_string(),
+        },
+    );
+}
+
+/// Encrypts a file into the output file a chunk at a time, so large files fit in memory.
+/// Nothing is written if the job is cancelled before the end.
+pub fn encrypt_file_to(
+    key: &Keyring,
+    file_path: &Path,
+    output_path: &Path,
+    cancelled: &AtomicBool,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
+    let (mut source, total) = open_source(file_path, cancelled)?;
+
+    write_atomic_with(output_path, |output| {
+        encrypt_stream_to(key, output_path, &mut source, output, |done| {
+            emit_progress(app_handle, file_path, done, total)
+        })
+    })
+}
+
+/// Decrypts a file into the output file a chunk at a time.
+/// Nothing is written if any chunk fails to decrypt or the job is cancelled.
+pub fn decrypt_file_to(
+    key: &Keyring,
+    file_path: &Path,
+    output_path: &Path,
+    cancelled: &AtomicBool,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
+    let (mut source, total) = open_source(file_path, cancelled)?;
+
+    write_atomic_with(output_path, |output| {
+        decrypt_stream_to(key, file_path, &mut source, output, |done| {
+            emit_progress(app_handle, file_path, done, total)
+        })
+    })
+}
 
 /// Opens a file from the given path, handling both encrypted and non-encrypted files.
 pub fn handle_path(file_path: &PathBuf, window: &Window) -> Result<(), String> {
@@ -16,6 +103,11 @@ pub fn handle_path(file_path: &PathBuf, window: &Window) -> Result<(), String> {
         .and_then(|ext| ext.to_str())
         .map_or(false, |ext| ext == "lockd");
 
+    // Archives of folders are restored to a folder
+    if file_path.is_file() && file_path.extension() == Some(ARCHIVE_EXTENSION.as_ref()) {
+        return extract_folder_archive(file_path, app_state, app_handle);
+    }
+
     match (is_lockd, file_path.is_dir()) {
         // .lockd directory - decrypt folder
         (true, true) => decrypt_folder(file_path, app_state, app_handle),
@@ -31,15 +123,31 @@ pub fn handle_path(file_path: &PathBuf, window: &Window) -> Result<(), String> {
     }
 }
 
-/// Encrypts a file at the given path and saves it with a .lockd extension.
+/// Encrypts a file at the given path and saves it with a .lockd extension in a background job
 pub fn encrypt_file(
-    file_path: &PathBuf,
+    file_path: &Path,
     app_state: State<Mutex<AppState>>,
     app_handle: &tauri::AppHandle,
 ) -> Result<(), String> {
     // Get the encryption key
     let key = app_state.lock().unwrap().get_encryption_key()?;
 
+    spawn_file_job(
+        file_path.to_path_buf(),
+        key,
+        app_handle.clone(),
+        pick_output_and_encrypt,
+    );
+
+    Ok(())
+}
+
+/// Shows the save dialog for the encrypted file and encrypts it
+fn pick_output_and_encrypt(
+    file_path: &Path,
+    key: &Keyring,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
     // Show save dialog
     let title = file_path
         .file_name()
@@ -52,34 +160,45 @@ pub fn encrypt_file(
         .set_title(&format!("Save encrypted file: {}", title))
         .add_filter(title, &["lockd"])
         .set_file_name(&format!("{}.lockd", title))
-        .set_directory(file_path.parent().unwrap_or(file_path.as_path()))
+        .set_directory(file_path.parent().unwrap_or(file_path))
         .blocking_save_file();
 
-    // Write the encrypted data back to the file
+    // Write the encrypted data to the chosen location
     if let Some(path) = save_path {
-        // Read the file content
-        let file_data =
-            std::fs::read(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
-
-        // Encrypt the content using the shared utility function
-        let encrypted_data = encrypt_data(&key, &file_data)?;
-
-        std::fs::write(path.as_path().unwrap(), encrypted_data)
-            .map_err(|e| format!("Failed to write file: {}", e))?;
+        let path = path
+            .into_path()
+            .map_err(|e| format!("Invalid path: {}", e))?;
+        run_file_job(FolderJobKind::Encrypt, file_path, &path, key, app_handle)?;
     }
 
     Ok(())
 }
 
-/// Decrypts a file at the given path and saves it without the .lockd extension.
+/// Decrypts a file at the given path and saves it without the .lockd extension in a background job
 pub fn decrypt_file(
-    file_path: &PathBuf,
+    file_path: &Path,
     app_state: State<Mutex<AppState>>,
     app_handle: &tauri::AppHandle,
 ) -> Result<(), String> {
     // Get the encryption key
     let key = app_state.lock().unwrap().get_encryption_key()?;
 
+    spawn_file_job(
+        file_path.to_path_buf(),
+        key,
+        app_handle.clone(),
+        pick_output_and_decrypt,
+    );
+
+    Ok(())
+}
+
+/// Shows the save dialog for the decrypted file and decrypts it
+fn pick_output_and_decrypt(
+    file_path: &Path,
+    key: &Keyring,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
     // Get the original filename without .lockd extension
     let original_filename = file_path
         .file_stem()
@@ -92,26 +211,38 @@ pub fn decrypt_file(
         .file()
         .set_title(&format!("Save decrypted file: {}", original_filename))
         .set_file_name(original_filename)
-        .set_directory(file_path.parent().unwrap_or(file_path.as_path()))
+        .set_directory(file_path.parent().unwrap_or(file_path))
         .blocking_save_file();
 
     // Write the decrypted data to the chosen location
     if let Some(path) = save_path {
-        // Read the file content
-        let file_data =
-            std::fs::read(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
-
-        // Decrypt the content using the shared utility function
-        let decrypted_content = decrypt_data(&key, &file_data)?;
-
-        std::fs::write(path.as_path().unwrap(), decrypted_content)
-            .map_err(|e| format!("Failed to write file: {}", e))?;
+        let path = path
+            .into_path()
+            .map_err(|e| format!("Invalid path: {}", e))?;
+        run_file_job(FolderJobKind::Decrypt, file_path, &path, key, app_handle)?;
     }
 
     Ok(())
 }
 
-/// Encrypts all files in a folder recursively
+/// Runs the dialog and the encryption or decryption of a single file outside of the window
+/// event thread, like the folder jobs, so a large file doesn't freeze the app.
+/// The file is a job once its output is chosen, the frontend also follows its file-progress events.
+fn spawn_file_job(
+    file_path: PathBuf,
+    key: Arc<Keyring>,
+    app_handle: AppHandle,
+    job: fn(&Path, &Keyring, &AppHandle) -> Result<(), String>,
+) {
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(err) = job(&file_path, &key, &app_handle) {
+            let error_msg = format!("Failed to process '{}':\n{}", file_path.display(), err);
+            let _ = app_handle.emit("error", error_msg);
+        }
+    });
+}
+
+/// Encrypts all files in a folder recursively in a background job
 pub fn encrypt_folder(
     folder_path: &PathBuf,
     app_state: State<Mutex<AppState>>,
@@ -120,185 +251,158 @@ pub fn encrypt_folder(
     // Get the encryption key
     let key = app_state.lock().unwrap().get_encryption_key()?;
 
-    // Get folder name for the dialog title
-    let folder_name = folder_path
-        .file_name()
-        .and_then(|s| s.to_str())
-        .unwrap_or("folder");
-
-    // Show directory dialog for output location
-    let output_dir = app_handle
-        .dialog()
-        .file()
-        .set_title(&format!(
-            "Select destination for encrypted folder: {}",
-            folder_name
-        ))
-        .set_directory(folder_path.parent().unwrap_or(folder_path.as_path()))
-        .blocking_pick_folder();
-
-    let Some(output_path) = output_dir else {
-        // It is ok to cancel the dialog
-        return Ok(());
-    };
-
-    // Create output folder name based on the original folder name
-    let output_folder_name = format!("{}.lockd", folder_name);
-
-    // Create the output folder path
-    let output_folder = output_path.as_path().unwrap().join(&output_folder_name);
-
-    std::fs::create_dir_all(&output_folder)
-        .map_err(|e| format!("Failed to create output directory: {}", e))?;
-
-    encrypt_folder_recursive(folder_path, &output_folder, &key)?;
+    spawn_folder_job(
+        FolderJobKind::Encrypt,
+        folder_path.clone(),
+        key,
+        app_handle.clone(),
+    );
 
     Ok(())
 }
 
-/// Recursively encrypts all files in a folder
-fn encrypt_folder_recursive(
-    source_dir: &PathBuf,
-    output_dir: &PathBuf,
-    key: &[u8; 32],
+/// Decrypts all files in a folder recursively in a background job
+pub fn decrypt_folder(
+    folder_path: &PathBuf,
+    app_state: State<Mutex<AppState>>,
+    app_handle: &tauri::AppHandle,
 ) -> Result<(), String> {
-    let entries = std::fs::read_dir(source_dir)
-        .map_err(|e| format!("Failed to read directory {}: {}", source_dir.display(), e))?;
-
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
-        let path = entry.path();
-
-        if path.is_file() {
-            // Encrypt file
-            let file_data = std::fs::read(&path)
-                .map_err(|e| format!("Failed to read file {}: {}", path.display(), e))?;
-
-            let encrypted_data = encrypt_data(key, &file_data)?;
-
-            let filename = path
-                .file_name()
-                .and_then(|s| s.to_str())
-                .unwrap_or("encrypted_file");
-
-            let output_file = output_dir.join(format!("{}.lockd", filename));
-
-            std::fs::write(&output_file, encrypted_data).map_err(|e| {
-                format!(
-                    "Failed to write encrypted file {}: {}",
-                    output_file.display(),
-                    e
-                )
-            })?;
-        } else if path.is_dir() {
-            // Create subdirectory and recurse
-            let dirname = path
-                .file_name()
-                .and_then(|s| s.to_str())
-                .unwrap_or("folder");
-
-            let sub_output_dir = output_dir.join(dirname);
-            std::fs::create_dir_all(&sub_output_dir)
-                .map_err(|e| format!("Failed to create subdirectory: {}", e))?;
+    // Get the encryption key
+    let key = app_state.lock().unwrap().get_encryption_key()?;
 
-            encrypt_folder_recursive(&path, &sub_output_dir, key)?;
-        }
-    }
+    spawn_folder_job(
+        FolderJobKind::Decrypt,
+        folder_path.clone(),
+        key,
+        app_handle.clone(),
+    );
 
     Ok(())
 }
 
-/// Decrypts all files in a folder recursively
-pub fn decrypt_folder(
-    folder_path: &PathBuf,
+/// Restores the folder of an encrypted archive in a background job
+pub fn extract_folder_archive(
+    archive_path: &Path,
     app_state: State<Mutex<AppState>>,
     app_handle: &tauri::AppHandle,
 ) -> Result<(), String> {
     // Get the encryption key
     let key = app_state.lock().unwrap().get_encryption_key()?;
 
-    // Create output folder name based on the original folder name
-    // Remove .lockd extension if present
+    spawn_folder_job(
+        FolderJobKind::Extract,
+        archive_path.to_path_buf(),
+        key,
+        app_handle.clone(),
+    );
+
+    Ok(())
+}
+
+/// Asks if a folder is packed into a single archive instead of a folder of encrypted files
+fn ask_archive(folder_path: &Path, app_handle: &AppHandle) -> bool {
     let folder_name = folder_path
-        .file_stem()
+        .file_name()
         .and_then(|s| s.to_str())
-        .unwrap_or("decrypted_folder");
+        .unwrap_or("folder");
 
-    // Show directory dialog for output location
-    let output_dir = app_handle
+    app_handle
         .dialog()
-        .file()
-        .set_title(&format!(
-            "Select destination for decrypted folder: {}",
+        .message(format!(
+            "Encrypt {} into a single archive? It hides the names, number and sizes \
+             of its files, but it has to be extracted to open them.",
             folder_name
         ))
-        .set_directory(folder_path.parent().unwrap_or(folder_path.as_path()))
-        .blocking_pick_folder();
-
-    let Some(output_path) = output_dir else {
-        // It is ok to cancel the dialog
-        return Ok(());
-    };
-
-    // Create the output folder path
-    let output_folder = output_path.as_path().unwrap().join(folder_name);
-
-    std::fs::create_dir_all(&output_folder)
-        .map_err(|e| format!("Failed to create output directory: {}", e))?;
-
-    decrypt_folder_recursive(folder_path, &output_folder, &key)?;
+        .title("Encrypt folder")
+        .buttons(MessageDialogButtons::OkCancelCustom(
+            "Single archive".to_string(),
+            "Separate files".to_string(),
+        ))
+        .blocking_show()
+}
 
-    Ok(())
+/// Asks for the destination of a folder and runs the job outside of the window event thread,
+/// so the app keeps responding. The drop handler already returned, so errors are emitted.
+fn spawn_folder_job(
+    kind: FolderJobKind,
+    folder_path: PathBuf,
+    key: Arc<Keyring>,
+    app_handle: AppHandle,
+) {
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(err) = pick_output_and_run(kind, &folder_path, &key, &app_handle) {
+            let error_msg = format!("Failed to process '{}':\n{}", folder_path.display(), err);
+            let _ = app_handle.emit("error", error_msg);
+        }
+    });
 }
 
-/// Recursively decrypts all files in a folder
-pub fn decrypt_folder_recursive(
-    source_dir: &PathBuf,
-    output_dir: &PathBuf,
-    key: &[u8; 32],
+/// Shows the directory dialog for the output location and runs the folder job
+fn pick_output_and_run(
+    kind: FolderJobKind,
+    folder_path: &Path,
+    key: &Keyring,
+    app_handle: &AppHandle,
 ) -> Result<(), String> {
-    let entries = std::fs::read_dir(source_dir)
-        .map_err(|e| format!("Failed to read directory {}: {}", source_dir.display(), e))?;
-
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
-        let path = entry.path();
-
-        if path.is_file() {
-            // Decrypt file
-            let file_data = std::fs::read(&path)
-                .map_err(|e| format!("Failed to read file {}: {}", path.display(), e))?;
-
-            let decrypted_data = decrypt_data(key, &file_data)?;
+    // A folder can also be packed into a single archive, which hides its structure
+    let kind = match kind {
+        FolderJobKind::Encrypt if ask_archive(folder_path, app_handle) => FolderJobKind::Archive,
+        kind => kind,
+    };
 
-            let filename = path
-                .file_stem()
+    // The encrypted folder gets the .lockd extension, the decrypted one loses it
+    let (output_folder_name, title) = match kind {
+        FolderJobKind::Archive => {
+            let folder_name = folder_path
+                .file_name()
                 .and_then(|s| s.to_str())
-                .unwrap_or("decrypted_file");
-
-            let output_file = output_dir.join(filename);
-
-            std::fs::write(&output_file, decrypted_data).map_err(|e| {
-                format!(
-                    "Failed to write decrypted file {}: {}",
-                    output_file.display(),
-                    e
-                )
-            })?;
-        } else if path.is_dir() {
-            // Create subdirectory and recurse
-            let dirname = path
-                .file_stem()
+                .unwrap_or("folder");
+            (
+                format!("{}.{}", folder_name, ARCHIVE_EXTENSION),
+                format!("Select destination for archive of folder: {}", folder_name),
+            )
+        }
+        FolderJobKind::Encrypt => {
+            let folder_name = folder_path
+                .file_name()
                 .and_then(|s| s.to_str())
                 .unwrap_or("folder");
+            (
+                format!("{}.lockd", folder_name),
+                format!("Select destination for encrypted folder: {}", folder_name),
+            )
+        }
+        FolderJobKind::Decrypt | FolderJobKind::Extract => {
+            let folder_name = folder_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("decrypted_folder");
+            (
+                folder_name.to_string(),
+                format!("Select destination for decrypted folder: {}", folder_name),
+            )
+        }
+    };
 
-            let sub_output_dir = output_dir.join(dirname);
-            std::fs::create_dir_all(&sub_output_dir)
-                .map_err(|e| format!("Failed to create subdirectory: {}", e))?;
+    let output_dir = app_handle
+        .dialog()
+        .file()
+        .set_title(&title)
+        .set_directory(folder_path.parent().unwrap_or(folder_path))
+        .blocking_pick_folder();
 
-            decrypt_folder_recursive(&path, &sub_output_dir, key)?;
-        }
+    let Some(output_path) = output_dir else {
+        // It is ok to cancel the dialog
+        return Ok(());
+    };
+    let output_path = output_path
+        .into_path()
+        .map_err(|e| format!("Invalid folder path: {}", e))?;
+
+    let output_path = output_path.join(output_folder_name);
+    match kind {
+        FolderJobKind::Archive => pack_folder(folder_path, &output_path, key, app_handle),
+        FolderJobKind::Extract => extract_archive(folder_path, &output_path, key, app_handle),
+        _ => run_folder_job(kind, folder_path, &output_path, key, app_handle),
     }
-
-    Ok(())
 }
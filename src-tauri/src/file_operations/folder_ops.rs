@@ -1,6 +1,15 @@
-use crate::state::{AppState, FileSystemItem};
-use std::{path::PathBuf, sync::Mutex};
-use tauri::{Emitter, Window};
+use crate::file_operations::{
+    name_ops::display_name,
+    vault_ops::{is_vault, open_vault},
+    watcher::watch_folder,
+};
+use crate::state::{compare_items, AppState, FileSystemItem};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tauri::{Emitter, Manager, Window};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons};
 
 /// Opens a  folder and loads its file structure into the sidebar
 pub fn open_folder(
@@ -8,13 +17,6 @@ pub fn open_folder(
     window: &Window,
     app_state: tauri::State<Mutex<AppState>>,
 ) -> Result<(), String> {
-    // Get folder name
-    let folder_name = folder_path
-        .file_name()
-        .and_then(|s| s.to_str())
-        .unwrap_or("Unknown Folder")
-        .to_string();
-
     let folder_path_str = folder_path
         .to_str()
         .ok_or("Invalid folder path encoding")?
@@ -30,6 +32,77 @@ pub fn open_folder(
         return Ok(()); // Folder already opened
     }
 
+    // A folder that isn't a vault yet is only made one if the user agrees
+    if !is_vault(folder_path) {
+        ask_create_vault(folder_path, window);
+        return Ok(());
+    }
+
+    // Unlock the data key of the vault before anything is read from it
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+    open_vault(folder_path, window, &key)?;
+
+    show_folder(folder_path, window, &app_state)
+}
+
+/// Asks before a folder becomes a vault, its notes are encrypted again with the key of the vault.
+/// The dialog doesn't block the window, the folder is opened when the user answers.
+fn ask_create_vault(folder_path: &Path, window: &Window) {
+    let folder_name = folder_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("folder");
+
+    let folder_path = folder_path.to_path_buf();
+    let window = window.clone();
+    window
+        .dialog()
+        .message(format!(
+            "{} is not a vault yet. Make it a vault? Its notes are encrypted again \
+             with a new key that your password and a recovery code unlock.",
+            folder_name
+        ))
+        .title("Create vault")
+        .buttons(MessageDialogButtons::OkCancelCustom(
+            "Create vault".to_string(),
+            "Cancel".to_string(),
+        ))
+        .show(move |create| {
+            if !create {
+                return;
+            }
+
+            let app_state = window.state::<Mutex<AppState>>();
+            let key = app_state.lock().unwrap().get_encryption_key();
+            let opened = key
+                .map_err(String::from)
+                .and_then(|key| open_vault(&folder_path, &window, &key))
+                .and_then(|()| show_folder(&folder_path, &window, &app_state));
+            if let Err(err) = opened {
+                let error_msg = format!("Failed to open '{}':\n{}", folder_path.display(), err);
+                window.emit("error", error_msg).unwrap();
+            }
+        });
+}
+
+/// Loads the file structure of an unlocked vault into the sidebar
+fn show_folder(
+    folder_path: &PathBuf,
+    window: &Window,
+    app_state: &tauri::State<Mutex<AppState>>,
+) -> Result<(), String> {
+    // Get folder name
+    let folder_name = folder_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("Unknown Folder")
+        .to_string();
+
+    let folder_path_str = folder_path
+        .to_str()
+        .ok_or("Invalid folder path encoding")?
+        .to_string();
+
     // Generate ID for the folder and add to mapping
     let folder_id = app_state
         .lock()
@@ -37,7 +110,10 @@ pub fn open_folder(
         .add_path_mapping(folder_path_str.clone());
 
     // Scan for complete file structure
-    let file_structure = scan_directory_structure(folder_path, &app_state, &folder_id)?;
+    let file_structure = scan_directory_structure(folder_path, app_state, &folder_id)?;
+
+    // Keep the tree updated with the changes made outside of the app
+    let watcher = watch_folder(folder_id.clone(), folder_path.clone(), window)?;
 
     let opened_folder = FileSystemItem {
         id: folder_id.clone(),
@@ -53,6 +129,7 @@ pub fn open_folder(
     let frontend_item = {
         let mut state = app_state.lock().unwrap();
         state.add_opened_item(&opened_folder.clone());
+        state.add_watcher(opened_folder.id.clone(), watcher);
         state.to_frontend_item(&opened_folder)
     };
 
@@ -72,69 +149,110 @@ pub fn scan_directory_structure(
 ) -> Result<Vec<FileSystemItem>, String> {
     let mut items = Vec::new();
 
-    let entries = std::fs::read_dir(folder_path)
-        .map_err(|e| format!("Failed to read directory {}: {}", folder_path.display(), e))?;
+    for path in read_entries(folder_path)? {
+        items.push(scan_item(&path, app_state, parent_id));
+    }
+
+    // Sort items: directories first, then files, both sorted alphabetically
+    items.sort_by(compare_items);
 
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
-        let path = entry.path();
+    Ok(items)
+}
 
-        let name = path
+/// Creates the item of a path and registers its id, directories are scanned recursively
+pub fn scan_item(
+    path: &PathBuf,
+    app_state: &tauri::State<Mutex<AppState>>,
+    parent_id: &String,
+) -> FileSystemItem {
+    // The names can be encrypted on disk
+    let name = match app_state.lock().unwrap().get_encryption_key() {
+        Ok(key) => display_name(&key, path),
+        Err(_) => path
             .file_name()
             .and_then(|s| s.to_str())
             .unwrap_or("Unknown")
-            .to_string();
-
-        let path_str = path.to_str().unwrap_or("").to_string();
-        let is_directory = path.is_dir();
-
-        // Generate ID and add to mapping
-        let item_id = app_state.lock().unwrap().add_path_mapping(path_str.clone());
-
-        // Check if it's a .lockd file
-        let is_note = if path.is_file() {
-            if let Some(extension) = path.extension() {
-                if extension == "lockd" {
-                    // Check if it's a note (single extension, not double like file.txt.lockd)
-                    let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
-                    !PathBuf::from(file_stem).extension().is_some()
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        } else {
-            false
-        };
-
-        // Recursively scan subdirectories
-        let children = if is_directory && !name.starts_with('.') {
-            match scan_directory_structure(&path, app_state, &parent_id) {
-                Ok(child_items) => Some(child_items),
-                Err(_) => None, // Skip directories we can't read
-            }
-        } else {
-            None
-        };
-
-        items.push(FileSystemItem {
-            id: item_id,
-            parent_id: parent_id.clone(),
-            name,
-            path: path_str,
-            is_directory,
-            is_note,
-            children,
-        });
+            .to_string(),
+    };
+
+    let path_str = path.to_str().unwrap_or("").to_string();
+    let is_directory = path.is_dir();
+
+    // Generate ID and add to mapping
+    let item_id = app_state.lock().unwrap().add_path_mapping(path_str.clone());
+
+    // Check if it's a .lockd file
+    let is_note = path.is_file() && is_note_path(path);
+
+    // Recursively scan subdirectories
+    let children = if is_directory && !is_hidden(path) {
+        match scan_directory_structure(path, app_state, parent_id) {
+            Ok(child_items) => Some(child_items),
+            Err(_) => None, // Skip directories we can't read
+        }
+    } else {
+        None
+    };
+
+    FileSystemItem {
+        id: item_id,
+        parent_id: parent_id.clone(),
+        name,
+        path: path_str,
+        is_directory,
+        is_note,
+        children,
     }
+}
 
-    // Sort items: directories first, then files, both sorted alphabetically
-    items.sort_by(|a, b| match (a.is_directory, b.is_directory) {
-        (true, false) => std::cmp::Ordering::Less,
-        (false, true) => std::cmp::Ordering::Greater,
-        _ => a.name.cmp(&b.name),
-    });
+/// Checks if a path is a note, a .lockd file with a single extension (not file.txt.lockd)
+pub fn is_note_path(path: &Path) -> bool {
+    if path
+        .extension()
+        .is_none_or(|extension| extension != "lockd")
+    {
+        return false;
+    }
 
-    Ok(items)
+    let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    Path::new(file_stem).extension().is_none()
+}
+
+/// Reads the paths of the entries of a directory
+pub fn read_entries(folder_path: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(folder_path)
+        .map_err(|e| format!("Failed to read directory {}: {}", folder_path.display(), e))?;
+
+    entries
+        .map(|entry| {
+            entry
+                .map(|entry| entry.path())
+                .map_err(|e| format!("Failed to read directory entry: {}", e))
+        })
+        .collect()
+}
+
+/// Checks if a path is hidden, the contents of hidden folders like .lockd are not scanned
+pub fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .is_some_and(|name| name.starts_with('.'))
+}
+
+/// Collects the encrypted files of a folder recursively,
+/// following the same rules as the scan of the sidebar
+pub fn collect_encrypted_files(folder_path: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+
+    for path in read_entries(folder_path)? {
+        if path.is_dir() {
+            if !is_hidden(&path) {
+                files.extend(collect_encrypted_files(&path)?);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "lockd") {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
 }
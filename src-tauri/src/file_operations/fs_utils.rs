@@ -0,0 +1,130 @@
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// What a file looked like when the app last read or wrote it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    size: u64,
+    hash: [u8; 32],
+}
+
+impl FileStamp {
+    /// Stamp of a file from the data just read from it or written to it
+    pub fn new(path: &Path, data: &[u8]) -> Self {
+        FileStamp {
+            modified: std::fs::metadata(path).and_then(|m| m.modified()).ok(),
+            size: data.len() as u64,
+            hash: Sha256::digest(data).into(),
+        }
+    }
+
+    /// Stamp of data that is about to be written, it is only compared by its content
+    pub fn from_data(data: &[u8]) -> Self {
+        FileStamp {
+            modified: None,
+            size: data.len() as u64,
+            hash: Sha256::digest(data).into(),
+        }
+    }
+
+    /// Checks if the file on disk is still the same.
+    /// The content is only hashed when the size or modification time changed,
+    /// so a file that was only touched is not reported as changed.
+    pub fn matches_file(&self, path: &Path) -> bool {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return false;
+        };
+        if metadata.len() != self.size {
+            return false;
+        }
+        if metadata.modified().ok() == self.modified {
+            return true;
+        }
+
+        match std::fs::read(path) {
+            Ok(data) => <[u8; 32]>::from(Sha256::digest(&data)) == self.hash,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Writes the data to a temporary file next to the destination, syncs it to disk
+/// and renames it over the destination, so the file is never left half written
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), String> {
+    write_atomic_with(path, |file| {
+        file.write_all(data)
+            .map_err(|e| format!("Failed to write file {}: {}", path.display(), e))
+    })
+}
+
+/// Like `write_atomic`, but the content is written by the function,
+/// so large files don't have to be kept in memory
+pub fn write_atomic_with(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> Result<(), String>,
+) -> Result<(), String> {
+    let parent = path.parent().ok_or("Invalid file path")?;
+    let file_name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or("Invalid file path")?;
+
+    // The temporary file must be in the same directory for the rename to be atomic
+    let tmp_path = parent.join(format!(".{}.{:016x}.tmp", file_name, rand::random::<u64>()));
+
+    let result = File::create_new(&tmp_path)
+        .and_then(|file| {
+            // Keep the permissions of the file being replaced
+            if let Ok(metadata) = std::fs::metadata(path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            Ok(file)
+        })
+        .map_err(|e| format!("Failed to write file {}: {}", path.display(), e))
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()
+                .and_then(|_| std::fs::rename(&tmp_path, path))
+                .map_err(|e| format!("Failed to write file {}: {}", path.display(), e))
+        });
+
+    // A file that wasn't written completely is never left behind
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+
+    // Sync the directory so the rename itself survives a crash
+    #[cfg(unix)]
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+
+    Ok(())
+}
+
+/// Whether two existing paths are the same file, like two cases of a name
+/// on a case-insensitive file system. On a case-sensitive one they are different files.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (std::fs::metadata(a), std::fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+
+    // The canonical path has the case of the name on disk
+    #[cfg(not(unix))]
+    {
+        match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use crate::encryption::{
+    decrypt_data, encrypt_data, keyring::Keyring, read_file_identity, retag_stream_to,
+};
+use crate::file_operations::{
+    fs_utils::{write_atomic, write_atomic_with},
+    note_ops::open_note_and_emit,
+    vault_ops::find_vault_root,
+};
+use crate::state::AppState;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{State, Window};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Name of the folder with the history of the notes, inside the .lockd folder
+const HISTORY_FOLDER: &str = "history";
+
+/// Above this size of the comparison table the diff doesn't look for common lines
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// How many old versions of each note are kept.
+/// A limit of 0 means no limit, if both are 0 no history is kept.
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryRetention {
+    pub max_versions: usize,
+    pub max_days: u64,
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        HistoryRetention {
+            max_versions: 20,
+            max_days: 30,
+        }
+    }
+}
+
+/// Old version of a note, from the time it was replaced
+#[derive(Clone, serde::Serialize)]
+pub struct NoteVersion {
+    /// Name of the version in the history, it identifies it
+    pub id: String,
+    pub timestamp: u64,
+    pub size: u64,
+}
+
+/// Line of the difference between two versions of a note
+#[derive(Clone, serde::Serialize)]
+#[serde(tag = "kind", content = "text", rename_all = "lowercase")]
+pub enum DiffLine {
+    Equal(String),
+    Added(String),
+    Removed(String),
+}
+
+// The lines are the decrypted content of the notes
+impl Zeroize for DiffLine {
+    fn zeroize(&mut self) {
+        match self {
+            DiffLine::Equal(text) | DiffLine::Added(text) | DiffLine::Removed(text) => {
+                text.zeroize()
+            }
+        }
+    }
+}
+
+/// Folder with the history of all the notes of a vault
+pub fn history_root(vault_root: &Path) -> PathBuf {
+    vault_root.join(".lockd").join(HISTORY_FOLDER)
+}
+
+/// Folder with the old versions of a note, or None if the note is not in a vault.
+/// The folder is named after a hash of the path so the history doesn't show the note names.
+pub fn history_dir(note_path: &Path) -> Option<PathBuf> {
+    let vault_root = find_vault_root(note_path)?;
+    let relative_path = note_path.strip_prefix(&vault_root).ok()?;
+
+    // Use the same separator on every platform so synced vaults keep their history
+    let relative_path = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    Some(history_root(&vault_root).join(hex::encode(Sha256::digest(relative_path.as_bytes()))))
+}
+
+/// Keeps the history of a note that was moved or renamed inside its vault.
+/// If the new location already has a history, from a note that was there before, they are merged.
+/// The versions are tagged with the location of the note, so they are encrypted again.
+pub fn move_history(key: &Keyring, note_path: &Path, new_path: &Path) -> Result<(), String> {
+    let (Some(dir), Some(new_dir)) = (history_dir(note_path), history_dir(new_path)) else {
+        return Ok(());
+    };
+    if !dir.exists() || dir == new_dir {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(&new_dir)
+        .map_err(|e| format!("Failed to create note history directory: {}", e))?;
+
+    for version in read_versions(&dir)? {
+        let path = version_path(&dir, &version.id);
+        let mut new_version_path = version_path(&new_dir, &version.id);
+        // Versions of older versions of the app have no suffix, the same time can be taken
+        if new_version_path.exists() {
+            new_version_path = version_path(&new_dir, &new_version_id(version.timestamp));
+        }
+
+        if read_file_identity(&path)?.is_none() {
+            std::fs::rename(&path, &new_version_path)
+                .map_err(|e| format!("Failed to move note version: {}", e))?;
+            continue;
+        }
+
+        // Only the keys and the tag change, large versions are encrypted again a chunk at a time
+        let mut source = std::fs::File::open(&path)
+            .map_err(|e| format!("Failed to read note version: {}", e))?;
+        write_atomic_with(&new_version_path, |output| {
+            retag_stream_to(key, note_path, new_path, &mut source, output)
+        })?;
+        std::fs::remove_file(&path)
+            .map_err(|e| format!("Failed to remove moved note version: {}", e))?;
+    }
+
+    std::fs::remove_dir_all(&dir).map_err(|e| format!("Failed to remove note history: {}", e))
+}
+
+/// Moves the versions of a history folder into another one of the same note,
+/// the versions are kept as they are. Used when a deleted note comes back from the trash.
+pub fn merge_history(dir: &Path, new_dir: &Path) -> Result<(), String> {
+    if !new_dir.exists() {
+        return std::fs::rename(dir, new_dir)
+            .map_err(|e| format!("Failed to move note history: {}", e));
+    }
+
+    for version in read_versions(dir)? {
+        let mut new_version_path = version_path(new_dir, &version.id);
+        if new_version_path.exists() {
+            new_version_path = version_path(new_dir, &new_version_id(version.timestamp));
+        }
+        std::fs::rename(version_path(dir, &version.id), new_version_path)
+            .map_err(|e| format!("Failed to move note version: {}", e))?;
+    }
+
+    std::fs::remove_dir_all(dir).map_err(|e| format!("Failed to remove note history: {}", e))
+}
+
+/// Milliseconds since the unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Name of a version saved at the given time.
+/// The random suffix keeps apart the versions saved in the same millisecond.
+fn new_version_id(timestamp: u64) -> String {
+    format!("{}-{}", timestamp, hex::encode(rand::random::<[u8; 4]>()))
+}
+
+/// Time a version was saved, from its name. Older versions of the app didn't add a suffix.
+fn version_timestamp(id: &str) -> Option<u64> {
+    let (timestamp, suffix) = id.split_once('-').unwrap_or((id, ""));
+    if !suffix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    timestamp.parse().ok()
+}
+
+/// Path of a version in the history folder of a note
+fn version_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.lockd", id))
+}
+
+/// Lists the versions in a history folder, newest first
+fn read_versions(dir: &Path) -> Result<Vec<NoteVersion>, String> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read note history {}: {}", dir.display(), e))?;
+
+    let mut versions: Vec<NoteVersion> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "lockd" {
+                return None;
+            }
+            let id = path.file_stem()?.to_str()?.to_string();
+            let timestamp = version_timestamp(&id)?;
+            let size = entry.metadata().ok()?.len();
+            Some(NoteVersion {
+                id,
+                timestamp,
+                size,
+            })
+        })
+        .collect();
+
+    versions.sort_by(|a, b| (b.timestamp, &b.id).cmp(&(a.timestamp, &a.id)));
+    Ok(versions)
+}
+
+/// Keeps the current content of a note in its history before it is overwritten.
+/// The content is copied as it is, so it stays encrypted.
+pub fn save_version(note_path: &Path, retention: HistoryRetention) -> Result<(), String> {
+    if retention.max_versions == 0 && retention.max_days == 0 {
+        return Ok(());
+    }
+
+    let Some(dir) = history_dir(note_path) else {
+        return Ok(());
+    };
+
+    // Nothing to keep for a new or empty note
+    let file_data = match std::fs::read(note_path) {
+        Ok(data) if !data.is_empty() => data,
+        _ => return Ok(()),
+    };
+
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create note history directory: {}", e))?;
+    write_atomic(
+        &version_path(&dir, &new_version_id(now_millis())),
+        &file_data,
+    )?;
+
+    prune_versions(&dir, retention)
+}
+
+/// Removes the versions over the limits of the retention
+fn prune_versions(dir: &Path, retention: HistoryRetention) -> Result<(), String> {
+    let oldest_kept = if retention.max_days > 0 {
+        // The days come from the config, a huge value keeps everything instead of overflowing
+        let max_age = Duration::from_secs(retention.max_days.saturating_mul(24 * 60 * 60));
+        now_millis().saturating_sub(u64::try_from(max_age.as_millis()).unwrap_or(u64::MAX))
+    } else {
+        0
+    };
+
+    for (index, version) in read_versions(dir)?.iter().enumerate() {
+        let too_many = retention.max_versions > 0 && index >= retention.max_versions;
+        if too_many || version.timestamp < oldest_kept {
+            std::fs::remove_file(version_path(dir, &version.id))
+                .map_err(|e| format!("Failed to remove old note version: {}", e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets the path of a note from its id
+fn note_path(id: &str, app_state: &State<Mutex<AppState>>) -> Result<PathBuf, String> {
+    app_state
+        .lock()
+        .unwrap()
+        .get_path_from_id(id)
+        .map(PathBuf::from)
+        .ok_or("Note not found".to_string())
+}
+
+/// Reads and decrypts a version of a note
+fn read_version(
+    note_path: &Path,
+    version_id: &str,
+    app_state: &State<Mutex<AppState>>,
+) -> Result<Zeroizing<String>, String> {
+    let dir = history_dir(note_path).ok_or("Only notes inside a vault have a history")?;
+    // The id comes from the frontend, it must be the name of a version
+    if version_timestamp(version_id).is_none() {
+        return Err("Invalid note version".to_string());
+    }
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+
+    let file_data = std::fs::read(version_path(&dir, version_id))
+        .map_err(|e| format!("Failed to read note version: {}", e))?;
+    // The versions are copies of the note, tagged with its location
+    Ok(decrypted_text(decrypt_data(&key, note_path, &file_data)?))
+}
+
+/// Turns decrypted content into text that is wiped from memory when it is dropped,
+/// the bytes that aren't text are replaced
+fn decrypted_text(content: Vec<u8>) -> Zeroizing<String> {
+    match String::from_utf8(content) {
+        Ok(text) => Zeroizing::new(text),
+        Err(err) => {
+            let content = Zeroizing::new(err.into_bytes());
+            Zeroizing::new(String::from_utf8_lossy(&content).into_owned())
+        }
+    }
+}
+
+#[tauri::command]
+/// Lists the old versions of a note, newest first
+pub fn list_note_versions(
+    id: &str,
+    app_state: State<Mutex<AppState>>,
+) -> Result<Vec<NoteVersion>, String> {
+    let note_path = note_path(id, &app_state)?;
+    match history_dir(&note_path) {
+        Some(dir) => read_versions(&dir),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[tauri::command]
+/// Returns the decrypted content of an old version of a note
+pub fn preview_note_version(
+    id: &str,
+    version_id: &str,
+    app_state: State<Mutex<AppState>>,
+) -> Result<Zeroizing<String>, String> {
+    let note_path = note_path(id, &app_state)?;
+    read_version(&note_path, version_id, &app_state)
+}
+
+#[tauri::command]
+/// Compares an old version of a note with the given content, or the saved note if there is none
+pub fn diff_note_version(
+    id: &str,
+    version_id: &str,
+    content: Option<String>,
+    app_state: State<Mutex<AppState>>,
+) -> Result<Zeroizing<Vec<DiffLine>>, String> {
+    let note_path = note_path(id, &app_state)?;
+    let old_content = read_version(&note_path, version_id, &app_state)?;
+
+    let new_content = match content {
+        Some(content) => Zeroizing::new(content),
+        None => {
+            let key = app_state.lock().unwrap().get_encryption_key()?;
+            let file_data =
+                std::fs::read(&note_path).map_err(|e| format!("Failed to read file: {}", e))?;
+            decrypted_text(decrypt_data(&key, &note_path, &file_data)?)
+        }
+    };
+
+    Ok(Zeroizing::new(diff_lines(&old_content, &new_content)))
+}
+
+#[tauri::command]
+/// Replaces a note with an old version and opens it again.
+/// The replaced content is kept in the history, so restoring can be undone.
+pub fn restore_note_version(
+    id: &str,
+    parent_id: &str,
+    version_id: &str,
+    history: Option<HistoryRetention>,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<(), String> {
+    let note_path = note_path(id, &app_state)?;
+    let content = read_version(&note_path, version_id, &app_state)?;
+
+    save_version(&note_path, history.unwrap_or_default())?;
+
+    // Encrypt it again, the version could have been written with older keys
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+    let file_data = encrypt_data(&key, &note_path, content.as_bytes())?;
+    write_atomic(&note_path, &file_data)?;
+
+    open_note_and_emit(
+        id.to_string(),
+        parent_id.to_string(),
+        &note_path,
+        &window,
+        &app_state,
+    )
+}
+
+/// Line by line difference of two texts, from the longest common subsequence of lines
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // The common start and end don't need to be compared
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut diff: Vec<DiffLine> = old[..prefix]
+        .iter()
+        .map(|line| DiffLine::Equal(line.to_string()))
+        .collect();
+
+    if (old_middle.len() + 1) * (new_middle.len() + 1) > MAX_DIFF_CELLS {
+        // Too big to compare, show it as fully replaced
+        diff.extend(old_middle.iter().map(|l| DiffLine::Removed(l.to_string())));
+        diff.extend(new_middle.iter().map(|l| DiffLine::Added(l.to_string())));
+    } else {
+        diff.extend(diff_middle(old_middle, new_middle));
+    }
+
+    diff.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| DiffLine::Equal(line.to_string())),
+    );
+
+    diff
+}
+
+/// Difference of two lists of lines with a longest common subsequence table
+fn diff_middle(old: &[&str], new: &[&str]) -> Vec<DiffLine> {
+    let width = new.len() + 1;
+
+    // lengths[i * width + j] is the length of the common subsequence of old[i..] and new[j..]
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            diff.push(DiffLine::Equal(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            diff.push(DiffLine::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    diff.extend(old[i..].iter().map(|l| DiffLine::Removed(l.to_string())));
+    diff.extend(new[j..].iter().map(|l| DiffLine::Added(l.to_string())));
+
+    diff
+}
@@ -0,0 +1,366 @@
+use crate::encryption::encrypt_data;
+use crate::encryption::keyring::Keyring;
+use crate::error::CommandError;
+use crate::file_operations::{
+    folder_ops::collect_encrypted_files,
+    fs_utils::{same_file, write_atomic, FileStamp},
+    name_ops::{display_name, item_path, unique_name},
+    note_ops::{open_note_and_emit, relocate_note_data},
+    vault_ops::find_vault_root,
+    watcher::add_item,
+};
+use crate::state::{AppState, FileSystemItemFrontend};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{Emitter, Manager, State, Window};
+
+/// An item was moved to another directory of its opened folder, it keeps its id
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemMoved {
+    pub root_id: String,
+    /// Id of the directory that now contains the item, the root id for the top level
+    pub parent_item_id: String,
+    pub item: FileSystemItemFrontend,
+}
+
+/// Opened folder and directory where a new item is created
+struct CreateTarget {
+    root_id: String,
+    root_path: PathBuf,
+    dir_path: PathBuf,
+}
+
+/// Finds the directory of an opened folder with the given id, it can be the folder itself
+fn find_target(
+    parent_id: &str,
+    app_state: &State<Mutex<AppState>>,
+) -> Result<CreateTarget, String> {
+    let state = app_state.lock().unwrap();
+
+    let root = state
+        .find_root_of(parent_id)
+        .filter(|root| root.is_directory)
+        .ok_or("Items can only be created inside an opened folder")?;
+    let dir_path = state
+        .get_path_from_id(parent_id)
+        .map(PathBuf::from)
+        .filter(|path| path.is_dir())
+        .ok_or("Folder not found")?;
+
+    Ok(CreateTarget {
+        root_id: root.id.clone(),
+        root_path: PathBuf::from(&root.path),
+        dir_path,
+    })
+}
+
+/// Characters that some platforms don't allow in file names
+const RESERVED_CHARACTERS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Names that Windows reserves for devices, with or without an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks that a name can be used for a note or a folder on every platform,
+/// so vaults can be synced between them
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("The name can't be empty".to_string());
+    }
+    if name.contains(['/', '\\']) {
+        return Err("The name can't contain slashes".to_string());
+    }
+    if let Some(c) = name.chars().find(|c| RESERVED_CHARACTERS.contains(c)) {
+        return Err(format!("The name can't contain '{}'", c));
+    }
+    if name.chars().any(char::is_control) {
+        return Err("The name can't contain control characters".to_string());
+    }
+    // Hidden items are not shown in the sidebar
+    if name.starts_with('.') {
+        return Err("The name can't start with a dot".to_string());
+    }
+    if name.ends_with(['.', ' ']) {
+        return Err("The name can't end with a dot or a space".to_string());
+    }
+
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return Err(format!("'{}' is a reserved name", stem));
+    }
+    Ok(())
+}
+
+/// Checks a note title, on top of `validate_name` it can't contain a dot:
+/// "a.txt.lockd" would be an encrypted file instead of a note
+pub fn validate_note_title(title: &str) -> Result<(), String> {
+    validate_name(title)?;
+    if title.contains('.') {
+        return Err("The title of a note can't contain dots".to_string());
+    }
+    Ok(())
+}
+
+/// Checks that a path is free for an item. A different case of its name is only the item itself
+/// if the file system resolves both to the same file. Suggests a free name if it is taken.
+pub fn check_free_path(key: &Keyring, path: &Path, item_path: &Path) -> Result<(), CommandError> {
+    if !path.exists() || same_file(path, item_path) {
+        return Ok(());
+    }
+
+    let name = display_name(key, path);
+    let dir = path.parent().ok_or("Invalid item path")?;
+    let suggestion = unique_name(key, dir, &name)?;
+
+    let without_extension = |name: &str| name.strip_suffix(".lockd").unwrap_or(name).to_string();
+    Err(CommandError::exists(
+        &without_extension(&name),
+        &without_extension(&suggestion),
+    ))
+}
+
+/// Adds a created path to the tree of its opened folder and returns its id.
+/// The watcher could have added it already.
+fn register_item(target: &CreateTarget, path: &Path, window: &Window) -> Result<String, String> {
+    add_item(window, &target.root_id, &target.root_path, path)?;
+
+    let path_str = path.to_string_lossy();
+    window
+        .state::<Mutex<AppState>>()
+        .lock()
+        .unwrap()
+        .find_child_by_path(&target.root_id, &path_str)
+        .map(|item| item.id.clone())
+        .ok_or("Failed to add the item to the folder".to_string())
+}
+
+#[tauri::command]
+/// Creates an empty encrypted note inside a folder of an opened vault and opens it.
+/// Returns the id of the note.
+pub fn create_note(
+    parent_id: &str,
+    title: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<String, String> {
+    let title = title.trim();
+    validate_note_title(title)?;
+
+    let target = find_target(parent_id, &app_state)?;
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+
+    let note_path = item_path(&key, &target.dir_path, &format!("{}.lockd", title))?;
+    if note_path.exists() {
+        return Err(format!("'{}' already exists", title));
+    }
+
+    let file_data = encrypt_data(&key, &note_path, &[])?;
+    write_atomic(&note_path, &file_data)?;
+
+    // The app wrote it, it is not an outside change
+    app_state.lock().unwrap().set_file_stamp(
+        note_path.to_string_lossy().to_string(),
+        FileStamp::new(&note_path, &file_data),
+    );
+
+    let id = register_item(&target, &note_path, &window)?;
+
+    open_note_and_emit(id.clone(), target.root_id, &note_path, &window, &app_state)?;
+
+    Ok(id)
+}
+
+#[tauri::command]
+/// Creates an empty folder inside a folder of an opened vault.
+/// Returns the id of the folder.
+pub fn create_folder(
+    parent_id: &str,
+    name: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<String, String> {
+    let name = name.trim();
+    validate_name(name)?;
+
+    let target = find_target(parent_id, &app_state)?;
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+
+    let folder_path = item_path(&key, &target.dir_path, name)?;
+    if folder_path.exists() {
+        return Err(format!("'{}' already exists", name));
+    }
+
+    std::fs::create_dir(&folder_path).map_err(|e| format!("Failed to create folder: {}", e))?;
+
+    register_item(&target, &folder_path, &window)
+}
+
+/// Paths of the encrypted files inside an item on disk, or the item itself if it is one.
+/// Every one of them is tagged with its location, not only the notes shown in the tree.
+fn encrypted_paths(item_path: &Path) -> Result<Vec<PathBuf>, String> {
+    match item_path.is_dir() {
+        true => collect_encrypted_files(item_path),
+        false if item_path.extension().is_some_and(|ext| ext == "lockd") => {
+            Ok(vec![item_path.to_path_buf()])
+        }
+        false => Ok(Vec::new()),
+    }
+}
+
+/// Renames or moves an item of the tree of an opened folder on disk and in the state.
+/// The lock of the state must be held so the watcher doesn't see the item disappear.
+/// Returns the event for the frontend and the encrypted files that have to be tagged again.
+pub fn relocate_item(
+    state: &mut AppState,
+    root_id: &str,
+    item_path: &Path,
+    new_path: &Path,
+) -> Result<(ItemMoved, Vec<(PathBuf, PathBuf)>), String> {
+    let item_path_str = item_path.to_str().ok_or("Invalid file path encoding")?;
+    let new_path_str = new_path.to_str().ok_or("Invalid file path encoding")?;
+
+    // The names can be encrypted on disk
+    let key = state.get_encryption_key()?;
+    let name = display_name(&key, new_path);
+
+    let notes = encrypted_paths(item_path)?
+        .into_iter()
+        .map(|note_path| {
+            let new_note_path = match note_path.strip_prefix(item_path) {
+                Ok(relative_path) if !relative_path.as_os_str().is_empty() => {
+                    new_path.join(relative_path)
+                }
+                _ => new_path.to_path_buf(),
+            };
+            (note_path, new_note_path)
+        })
+        .collect();
+
+    std::fs::rename(item_path, new_path).map_err(|e| format!("Failed to move item: {}", e))?;
+
+    let parent_item_id = state
+        .move_child(root_id, item_path_str, new_path_str, name)
+        .ok_or("Failed to move the item in the folder")?;
+    let item = state
+        .find_child_by_path(root_id, new_path_str)
+        .map(|item| state.to_frontend_item(item))
+        .ok_or("Failed to move the item in the folder")?;
+
+    let moved = ItemMoved {
+        root_id: root_id.to_string(),
+        parent_item_id,
+        item,
+    };
+    Ok((moved, notes))
+}
+
+/// Moves the history of the relocated notes, tags them with their new location
+/// and tells the frontend where the item is now
+pub fn finish_relocation(
+    moved: ItemMoved,
+    notes: Vec<(PathBuf, PathBuf)>,
+    window: &Window,
+) -> Result<(), String> {
+    let app_state = window.state::<Mutex<AppState>>();
+
+    // The history is found by the path of the note
+    for (note_path, new_note_path) in notes {
+        relocate_note_data(&note_path, &new_note_path, &app_state)?;
+    }
+
+    window
+        .emit("item-moved", moved)
+        .map_err(|e| format!("Failed to emit item-moved event: {}", e))
+}
+
+#[tauri::command]
+/// Moves a note or folder to another folder of the same opened folder.
+/// The item keeps its id, so an opened note stays open.
+pub fn move_item(
+    id: &str,
+    target_id: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<(), CommandError> {
+    let target = find_target(target_id, &app_state)?;
+
+    let mut state = app_state.lock().unwrap();
+
+    let root_id = state
+        .find_root_of(id)
+        .filter(|root| root.id != id)
+        .map(|root| root.id.clone())
+        .ok_or("Only items inside an opened folder can be moved")?;
+    if root_id != target.root_id {
+        return Err("Items can only be moved inside their opened folder".into());
+    }
+
+    let source_path = PathBuf::from(state.get_path_from_id(id).ok_or("Item not found")?);
+    if source_path.parent() == Some(target.dir_path.as_path()) {
+        return Ok(());
+    }
+    if target.dir_path.starts_with(&source_path) {
+        return Err("A folder can't be moved inside itself".into());
+    }
+
+    // An encrypted name depends on its directory, the item is named again in the target.
+    // An item with the same name is found even if its name is encrypted.
+    let key = state.get_encryption_key()?;
+    let name = display_name(&key, &source_path);
+    let new_path = item_path(&key, &target.dir_path, &name)?;
+
+    // The files are encrypted with the key of their vault
+    if find_vault_root(&source_path) != find_vault_root(&new_path) {
+        return Err("Items can't be moved to another vault".into());
+    }
+    check_free_path(&key, &new_path, &source_path)?;
+
+    let (moved, notes) = relocate_item(&mut state, &root_id, &source_path, &new_path)?;
+    drop(state);
+
+    Ok(finish_relocation(moved, notes, &window)?)
+}
+
+#[tauri::command]
+/// Renames a folder inside an opened folder, the items inside keep their ids
+pub fn rename_folder(
+    id: &str,
+    new_name: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<(), CommandError> {
+    let new_name = new_name.trim();
+    validate_name(new_name)?;
+
+    let mut state = app_state.lock().unwrap();
+
+    let root_id = state
+        .find_root_of(id)
+        .filter(|root| root.id != id)
+        .map(|root| root.id.clone())
+        .ok_or("Only folders inside an opened folder can be renamed")?;
+
+    let folder_path = PathBuf::from(state.get_path_from_id(id).ok_or("Folder not found")?);
+    if !folder_path.is_dir() {
+        return Err("Folder not found".into());
+    }
+
+    let key = state.get_encryption_key()?;
+    let parent_dir = folder_path.parent().ok_or("Invalid folder path")?;
+    let new_path = item_path(&key, parent_dir, new_name)?;
+    if new_path == folder_path {
+        return Ok(());
+    }
+    check_free_path(&key, &new_path, &folder_path)?;
+
+    let (moved, notes) = relocate_item(&mut state, &root_id, &folder_path, &new_path)?;
+    drop(state);
+
+    Ok(finish_relocation(moved, notes, &window)?)
+}
@@ -0,0 +1,334 @@
+use crate::encryption::keyring::Keyring;
+use crate::file_operations::encryption_ops::{decrypt_file_to, encrypt_file_to};
+use crate::state::AppState;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+/// What a folder job does with the files of the folder
+#[derive(Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FolderJobKind {
+    Encrypt,
+    Decrypt,
+    /// Packs the folder into a single encrypted archive
+    Archive,
+    /// Restores the folder of an encrypted archive
+    Extract,
+}
+
+impl FolderJobKind {
+    /// Name in the output folder of an item of the source folder
+    fn output_name(self, path: &Path) -> String {
+        match self {
+            FolderJobKind::Encrypt | FolderJobKind::Archive => {
+                let name = path
+                    .file_name()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("encrypted_file");
+                match path.is_dir() {
+                    true => name.to_string(),
+                    false => format!("{}.lockd", name),
+                }
+            }
+            // The .lockd extension is removed from files and folders
+            FolderJobKind::Decrypt | FolderJobKind::Extract => path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("decrypted_file")
+                .to_string(),
+        }
+    }
+}
+
+/// A folder job started, sent once the files to process are known
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FolderJobStarted {
+    job_id: String,
+    kind: FolderJobKind,
+    path: String,
+    total: usize,
+}
+
+/// Progress of a folder job, there is an event for each file
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FolderJobProgress {
+    job_id: String,
+    done: usize,
+    total: usize,
+    path: String,
+}
+
+/// A file that couldn't be encrypted or decrypted
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FailedFile {
+    path: String,
+    error: String,
+}
+
+/// Report of a folder job, sent when it finished or was cancelled
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FolderJobReport {
+    job_id: String,
+    kind: FolderJobKind,
+    path: String,
+    output: String,
+    done: usize,
+    total: usize,
+    cancelled: bool,
+    failed: Vec<FailedFile>,
+}
+
+/// Lists the files of the source folder with their path in the output folder.
+/// The folders are created on the way, so empty folders are kept too.
+/// Folders that can't be read or created are reported and skipped.
+fn collect_job_files(
+    kind: FolderJobKind,
+    source_dir: &Path,
+    output_dir: &Path,
+    files: &mut Vec<(PathBuf, PathBuf)>,
+    failed: &mut Vec<FailedFile>,
+) {
+    let mut fail = |path: &Path, error: String| {
+        failed.push(FailedFile {
+            path: path.to_string_lossy().to_string(),
+            error,
+        })
+    };
+
+    if let Err(e) = std::fs::create_dir_all(output_dir) {
+        fail(
+            source_dir,
+            format!("Failed to create output directory: {}", e),
+        );
+        return;
+    }
+
+    let entries = match std::fs::read_dir(source_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            fail(source_dir, format!("Failed to read directory: {}", e));
+            return;
+        }
+    };
+
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                fail(source_dir, format!("Failed to read directory entry: {}", e));
+                continue;
+            }
+        };
+
+        let output_path = output_dir.join(kind.output_name(&path));
+        if path.is_file() {
+            files.push((path, output_path));
+        } else if path.is_dir() {
+            subdirs.push((path, output_path));
+        }
+    }
+
+    for (path, output_path) in subdirs {
+        collect_job_files(kind, &path, &output_path, files, failed);
+    }
+}
+
+/// A running job, it sends the events of the job to the frontend.
+/// `finish` must be called at the end, even if the job failed.
+pub struct Job<'a> {
+    app_handle: &'a AppHandle,
+    id: String,
+    cancelled: Arc<AtomicBool>,
+    kind: FolderJobKind,
+    path: PathBuf,
+    output: PathBuf,
+    done: usize,
+    total: usize,
+    failed: Vec<FailedFile>,
+}
+
+impl<'a> Job<'a> {
+    /// Registers a job that processes the path into the output, so it can be cancelled
+    /// and the app doesn't lock itself while it runs
+    pub fn new(
+        kind: FolderJobKind,
+        path: &Path,
+        output: &Path,
+        app_handle: &'a AppHandle,
+    ) -> Result<Self, String> {
+        let (id, cancelled) = app_handle
+            .state::<Mutex<AppState>>()
+            .lock()
+            .unwrap()
+            .add_job()?;
+        Ok(Job {
+            app_handle,
+            id,
+            cancelled,
+            kind,
+            path: path.to_path_buf(),
+            output: output.to_path_buf(),
+            done: 0,
+            total: 0,
+            failed: Vec::new(),
+        })
+    }
+
+    /// Starts the job once the number of files to process is known.
+    /// The events before the report are only informative, so failures are ignored.
+    pub fn start(&mut self, total: usize) {
+        self.total = total;
+        let _ = self.app_handle.emit(
+            "folder-job-started",
+            FolderJobStarted {
+                job_id: self.id.clone(),
+                kind: self.kind,
+                path: self.path.to_string_lossy().to_string(),
+                total,
+            },
+        );
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Flag set when the job is cancelled, to stop in the middle of a file
+    pub fn cancel_flag(&self) -> &AtomicBool {
+        &self.cancelled
+    }
+
+    /// Reports a file that couldn't be processed, the job goes on
+    pub fn fail(&mut self, path: &Path, error: String) {
+        self.failed.push(FailedFile {
+            path: path.to_string_lossy().to_string(),
+            error,
+        });
+    }
+
+    /// Counts a file as processed, whether it failed or not
+    pub fn file_done(&mut self, path: &Path) {
+        self.done += 1;
+        let _ = self.app_handle.emit(
+            "folder-job-progress",
+            FolderJobProgress {
+                job_id: self.id.clone(),
+                done: self.done,
+                total: self.total,
+                path: path
+                    .strip_prefix(&self.path)
+                    .or_else(|_| path.strip_prefix(&self.output))
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .to_string(),
+            },
+        );
+    }
+
+    /// Unregisters the job and sends its report
+    pub fn finish(self) -> Result<(), String> {
+        let app_state = self.app_handle.state::<Mutex<AppState>>();
+        app_state.lock().unwrap().remove_job(&self.id);
+
+        self.app_handle
+            .emit(
+                "folder-job-finished",
+                FolderJobReport {
+                    job_id: self.id,
+                    kind: self.kind,
+                    path: self.path.to_string_lossy().to_string(),
+                    output: self.output.to_string_lossy().to_string(),
+                    done: self.done,
+                    total: self.total,
+                    cancelled: self.cancelled.load(Ordering::Relaxed),
+                    failed: self.failed,
+                },
+            )
+            .map_err(|e| format!("Failed to emit event: {}", e))
+    }
+}
+
+/// Encrypts or decrypts every file of a folder into the output folder.
+/// A file that fails is reported at the end instead of stopping the job,
+/// and the job stops before the next file when it is cancelled.
+pub fn run_folder_job(
+    kind: FolderJobKind,
+    folder_path: &Path,
+    output_folder: &Path,
+    key: &Keyring,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
+    let mut job = Job::new(kind, folder_path, output_folder, app_handle)?;
+
+    let mut files = Vec::new();
+    let mut failed = Vec::new();
+    collect_job_files(kind, folder_path, output_folder, &mut files, &mut failed);
+    job.failed = failed;
+    job.start(files.len());
+
+    for (file_path, output_path) in &files {
+        if job.is_cancelled() {
+            break;
+        }
+
+        process_file(kind, file_path, output_path, key, &mut job);
+    }
+
+    job.finish()
+}
+
+/// Encrypts or decrypts a single file into the output file as a job,
+/// so it is reported like the folders and the app waits for it before locking
+pub fn run_file_job(
+    kind: FolderJobKind,
+    file_path: &Path,
+    output_path: &Path,
+    key: &Keyring,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
+    let mut job = Job::new(kind, file_path, output_path, app_handle)?;
+    job.start(1);
+    process_file(kind, file_path, output_path, key, &mut job);
+    job.finish()
+}
+
+/// Encrypts or decrypts a file of a job, a file stopped by the cancel is not a failure
+fn process_file(
+    kind: FolderJobKind,
+    file_path: &Path,
+    output_path: &Path,
+    key: &Keyring,
+    job: &mut Job,
+) {
+    let cancelled = job.cancel_flag();
+    let result = match kind {
+        FolderJobKind::Encrypt | FolderJobKind::Archive => {
+            encrypt_file_to(key, file_path, output_path, cancelled, job.app_handle)
+        }
+        FolderJobKind::Decrypt | FolderJobKind::Extract => {
+            decrypt_file_to(key, file_path, output_path, cancelled, job.app_handle)
+        }
+    };
+    if let Err(error) = result {
+        if !job.is_cancelled() {
+            job.fail(file_path, error);
+        }
+    }
+    job.file_done(file_path);
+}
+
+#[tauri::command]
+/// Stops a job, the file being processed is discarded and the files already written are kept
+pub fn cancel_folder_job(job_id: &str, app_state: State<Mutex<AppState>>) -> Result<(), String> {
+    if !app_state.lock().unwrap().cancel_job(job_id) {
+        return Err("The job already finished".to_string());
+    }
+    Ok(())
+}
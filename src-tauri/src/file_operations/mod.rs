@@ -1,6 +1,16 @@
+pub mod archive_ops;
 pub mod commands;
 pub mod config_ops;
+pub mod conflict_ops;
 pub mod drag_drop;
 pub mod encryption_ops;
 pub mod folder_ops;
+pub mod fs_utils;
+pub mod history_ops;
+pub mod item_ops;
+pub mod job_ops;
+pub mod name_ops;
 pub mod note_ops;
+pub mod trash_ops;
+pub mod vault_ops;
+pub mod watcher;
@@ -0,0 +1,249 @@
+use crate::encryption::keyring::Keyring;
+use crate::file_operations::{
+    folder_ops::{is_hidden, open_folder, read_entries},
+    note_ops::relocate_note_data,
+    vault_ops::{load_keyfile, save_keyfile},
+};
+use crate::state::AppState;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{Emitter, State, Window};
+
+/// Extension of the notes, it stays visible when their names are encrypted
+const NOTE_EXTENSION: &str = ".lockd";
+
+/// Splits the extension of a note from its name
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.strip_suffix(NOTE_EXTENSION) {
+        Some(stem) if !stem.is_empty() => (stem, NOTE_EXTENSION),
+        _ => (name, ""),
+    }
+}
+
+/// Name of an item to show in the app.
+/// Names encrypted by an opened vault are decrypted, any other name is shown as it is.
+pub fn display_name(key: &Keyring, path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let Some((name_key, _, _)) = key.name_key_for_path(path) else {
+        return file_name;
+    };
+
+    let (stem, extension) = split_extension(&file_name);
+    match name_key.decrypt(stem) {
+        Some(name) => format!("{}{}", name, extension),
+        None => file_name,
+    }
+}
+
+/// Directory of a name relative to the root of its vault, with the same separator on every platform
+fn name_dir(root: &Path, dir: &Path) -> String {
+    dir.strip_prefix(root)
+        .unwrap_or(dir)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Name on disk of an item, encrypted if the vault of the directory encrypts names
+pub fn disk_name(key: &Keyring, dir: &Path, name: &str) -> Result<String, String> {
+    match key.name_key_for_path(dir) {
+        Some((name_key, true, root)) => {
+            let (stem, extension) = split_extension(name);
+            let encrypted = name_key.encrypt(&name_dir(&root, dir), stem)?;
+            Ok(format!("{}{}", encrypted, extension))
+        }
+        _ => Ok(name.to_string()),
+    }
+}
+
+/// Path on disk of an item with the given name in a directory.
+/// In a vault, an item that already has the name is looked up first: an encrypted name depends
+/// on the path of its directory when it was written, which changes when a parent is renamed.
+pub fn item_path(key: &Keyring, dir: &Path, name: &str) -> Result<PathBuf, String> {
+    if key.name_key_for_path(dir).is_some() {
+        if let Some(path) = find_item(key, dir, name) {
+            return Ok(path);
+        }
+    }
+    Ok(dir.join(disk_name(key, dir, name)?))
+}
+
+/// Finds the item of a directory with the given name, encrypted or not
+fn find_item(key: &Keyring, dir: &Path, name: &str) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| display_name(key, path) == name)
+}
+
+/// First name that is free in a directory, "name", "name (2)"...
+/// Notes keep their extension at the end.
+pub fn unique_name(key: &Keyring, dir: &Path, name: &str) -> Result<String, String> {
+    let (stem, extension) = split_extension(name);
+
+    for n in 1..1000 {
+        let candidate = match n {
+            1 => name.to_string(),
+            n => format!("{} ({}){}", stem, n, extension),
+        };
+        if !item_path(key, dir, &candidate)?.exists() {
+            return Ok(candidate);
+        }
+    }
+
+    Err(format!("Too many items named '{}'", name))
+}
+
+/// Renames the items inside a directory so their names are encrypted or not,
+/// the contents of the subdirectories first. Adds the notes that were renamed,
+/// also the ones renamed before an error, so their history and tags can follow them.
+fn convert_names(
+    key: &Keyring,
+    dir: &Path,
+    encrypt_names: bool,
+    renamed_notes: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<(), String> {
+    for path in read_entries(dir)? {
+        if is_hidden(&path) {
+            continue;
+        }
+
+        let mut notes = Vec::new();
+        let converted = match path.is_dir() {
+            true => convert_names(key, &path, encrypt_names, &mut notes),
+            false => {
+                if path.extension().is_some_and(|ext| ext == "lockd") {
+                    notes.push((path.clone(), path.clone()));
+                }
+                Ok(())
+            }
+        };
+
+        // The notes inside stay under the old path if the item isn't renamed
+        let renamed = converted.and_then(|_| convert_name(key, dir, &path, encrypt_names));
+        let new_path = renamed.as_ref().unwrap_or(&path);
+
+        // The notes inside are now under the new path
+        for (old_note_path, note_path) in notes {
+            let relative_path = note_path.strip_prefix(&path).unwrap_or(Path::new(""));
+            let note_path = match relative_path.as_os_str().is_empty() {
+                true => new_path.clone(),
+                false => new_path.join(relative_path),
+            };
+            renamed_notes.push((old_note_path, note_path));
+        }
+
+        renamed?;
+    }
+
+    Ok(())
+}
+
+/// Renames an item of a directory so its name is encrypted or not, returns its new path
+fn convert_name(
+    key: &Keyring,
+    dir: &Path,
+    path: &Path,
+    encrypt_names: bool,
+) -> Result<PathBuf, String> {
+    let name = display_name(key, path);
+    let new_name = match key.name_key_for_path(dir) {
+        Some((name_key, true, root)) if encrypt_names => {
+            let (stem, extension) = split_extension(&name);
+            let encrypted = name_key.encrypt(&name_dir(&root, dir), stem)?;
+            format!("{}{}", encrypted, extension)
+        }
+        _ => name.clone(),
+    };
+    let new_path = dir.join(&new_name);
+
+    if new_path != path {
+        if new_path.exists() {
+            return Err(format!("'{}' already exists in {}", name, dir.display()));
+        }
+        std::fs::rename(path, &new_path)
+            .map_err(|e| format!("Failed to rename {}: {}", name, e))?;
+    }
+
+    Ok(new_path)
+}
+
+#[tauri::command]
+/// Returns whether the names of an opened vault are encrypted on disk
+pub fn get_name_encryption(
+    folder_id: &str,
+    app_state: State<Mutex<AppState>>,
+) -> Result<bool, String> {
+    let folder_path = app_state
+        .lock()
+        .unwrap()
+        .get_path_from_id(folder_id)
+        .ok_or("Folder not found")?;
+
+    Ok(load_keyfile(Path::new(&folder_path))?.is_some_and(|keyfile| keyfile.encrypt_names))
+}
+
+#[tauri::command]
+/// Encrypts or decrypts the names of the notes and folders of an opened vault.
+/// Every item is renamed, so the folder is closed and opened again.
+pub fn set_name_encryption(
+    folder_id: &str,
+    encrypt_names: bool,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<(), String> {
+    let (key, folder_path) = {
+        let state = app_state.lock().unwrap();
+        let root = state
+            .find_root_of(folder_id)
+            .filter(|root| root.id == folder_id && root.is_directory)
+            .ok_or("Folder not found")?;
+        (state.get_encryption_key()?, PathBuf::from(&root.path))
+    };
+
+    let mut keyfile = load_keyfile(&folder_path)?.ok_or("The folder is not an unlocked vault")?;
+    if keyfile.encrypt_names == encrypt_names {
+        return Ok(());
+    }
+
+    // Close the folder first so the watcher doesn't follow every rename
+    app_state.lock().unwrap().remove_opened_item(folder_id);
+    window
+        .emit("item-closed", folder_id)
+        .map_err(|e| format!("Failed to emit item-closed event: {}", e))?;
+
+    // The keyring decides how new names are written
+    key.set_encrypt_names(&folder_path, encrypt_names);
+    let mut renamed_notes = Vec::new();
+    let mut result = convert_names(&key, &folder_path, encrypt_names, &mut renamed_notes);
+
+    // The history is found by the path of the note, and the notes are tagged with it.
+    // Every renamed note is relocated, even after an error.
+    for (note_path, new_note_path) in renamed_notes {
+        if note_path == new_note_path {
+            continue;
+        }
+        let relocated = relocate_note_data(&note_path, &new_note_path, &app_state);
+        result = result.and(relocated);
+    }
+
+    // The setting only changes once every item is converted,
+    // so the same change can be made again to convert the items that failed
+    match result {
+        Ok(()) => {
+            keyfile.encrypt_names = encrypt_names;
+            save_keyfile(&folder_path, &keyfile)?;
+        }
+        Err(_) => key.set_encrypt_names(&folder_path, keyfile.encrypt_names),
+    }
+
+    open_folder(&folder_path, &window, app_state)?;
+
+    result
+}
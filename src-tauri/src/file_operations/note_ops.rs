@@ -1,9 +1,20 @@
 use crate::{
-    encryption::decrypt_data,
+    encryption::{decrypt_data, encrypt_data, read_file_identity, retag_stream_to},
+    error::CommandError,
+    file_operations::{
+        fs_utils::{write_atomic, write_atomic_with, FileStamp},
+        history_ops::{move_history, save_version, HistoryRetention},
+        name_ops::display_name,
+        vault_ops::unlock_vault_of,
+    },
     state::{AppState, FileSystemItem},
 };
-use std::{path::PathBuf, sync::Mutex};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 use tauri::{Emitter, State, Window};
+use zeroize::{Zeroize, Zeroizing};
 
 /// Opens an encrypted note and emits the content to the frontend
 pub fn open_note_from_path(
@@ -16,6 +27,10 @@ pub fn open_note_from_path(
         .ok_or("Invalid file path encoding")?
         .to_string();
 
+    // A note of a vault can be opened without its folder, the vault is unlocked for it
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+    unlock_vault_of(file_path, window, &key)?;
+
     // Check if the file path is already opened
     // You cant put this in the if statement because it causes a deadlock
     let existing_id = app_state.lock().unwrap().is_opened(file_path_str.clone());
@@ -84,31 +99,147 @@ pub fn open_note_and_emit(
     // Decrypt the note
     let content = open_encrypted_note(file_path_str, &app_state)?;
 
-    let title = file_path
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("Untitled");
+    // The name can be encrypted on disk
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+    let name = display_name(&key, file_path);
+    let title = name.strip_suffix(".lockd").unwrap_or(&name);
 
     window
-        .emit("note-opened", (title, content, id, parent_id))
+        .emit("note-opened", (title, content.as_str(), id, parent_id))
         .map_err(|e| format!("Failed to emit event: {}", e))?;
 
     Ok(())
 }
 
 /// Opens an encrypted note from the specified file path and returns its decrypted content.
+/// The content is wiped from memory when it is dropped.
 pub fn open_encrypted_note(
     file_path: &str,
     app_state: &State<Mutex<AppState>>,
-) -> Result<String, String> {
+) -> Result<Zeroizing<String>, String> {
     // Get the encryption key
     let key = app_state.lock().unwrap().get_encryption_key()?;
 
     // Read the file content
     let file_data = std::fs::read(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
 
+    // Remember what the file looked like to detect changes made outside of the app
+    app_state.lock().unwrap().set_file_stamp(
+        file_path.to_string(),
+        FileStamp::new(Path::new(file_path), &file_data),
+    );
+
     // Decrypt the content
-    let decrypted_content = decrypt_data(&key, &file_data)?;
+    let decrypted_content = decrypt_data(&key, Path::new(file_path), &file_data)?;
+
+    // The bytes become the string without a copy, the ones that aren't text are wiped here
+    let content = String::from_utf8(decrypted_content).unwrap_or_else(|err| {
+        err.into_bytes().zeroize();
+        String::new()
+    });
+    Ok(Zeroizing::new(content))
+}
+
+/// Moves the history of a note that the app moved or renamed and encrypts it again,
+/// so it still matches its location in the vault. Files without an identity tag are kept.
+pub fn relocate_note_data(
+    note_path: &Path,
+    new_path: &Path,
+    app_state: &Mutex<AppState>,
+) -> Result<(), String> {
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+    move_history(&key, note_path, new_path)?;
+
+    if read_file_identity(new_path)?.is_none() {
+        return Ok(());
+    }
+    let mut source = std::fs::File::open(new_path)
+        .map_err(|e| format!("Failed to read file {}: {}", new_path.display(), e))?;
+
+    // Only the keys and the tag change, large files are encrypted again a chunk at a time
+    let path_str = new_path.to_string_lossy().to_string();
+    let tracked = app_state
+        .lock()
+        .unwrap()
+        .get_file_stamp(&path_str)
+        .is_some();
+    if !tracked {
+        return write_atomic_with(new_path, |output| {
+            retag_stream_to(&key, note_path, new_path, &mut source, output)
+        });
+    }
 
-    Ok(String::from_utf8(decrypted_content).unwrap_or_default())
+    // An open note is encrypted in memory, the watcher must know the new content
+    // before it is written so it isn't reported as an outside change
+    let mut file_data = Vec::new();
+    retag_stream_to(&key, note_path, new_path, &mut source, &mut file_data)?;
+    app_state
+        .lock()
+        .unwrap()
+        .set_file_stamp(path_str.clone(), FileStamp::from_data(&file_data));
+
+    write_atomic(new_path, &file_data)?;
+
+    app_state
+        .lock()
+        .unwrap()
+        .set_file_stamp(path_str, FileStamp::new(new_path, &file_data));
+
+    Ok(())
+}
+
+/// Encrypts a note and writes it to its file, keeping the previous version in the history.
+/// Unless it is forced, it fails if the file was changed since the app last read or wrote it.
+pub fn write_note(
+    id: &str,
+    content: &str,
+    history: HistoryRetention,
+    force: bool,
+    app_state: &State<Mutex<AppState>>,
+) -> Result<(), CommandError> {
+    // Get the encryption key and the path of the note
+    let (key, file_path) = {
+        let state = app_state.lock().unwrap();
+        let key = state.get_encryption_key()?;
+        let file_path = state.get_path_from_id(id).ok_or("Note not found")?;
+        (key, file_path)
+    };
+
+    // Notes that were never read or written by the app have nothing to compare
+    let path_buf = PathBuf::from(&file_path);
+    if !force {
+        if let Some(stamp) = app_state.lock().unwrap().get_file_stamp(&file_path) {
+            if !stamp.matches_file(&path_buf) {
+                return Err(CommandError::conflict(id));
+            }
+        }
+    }
+
+    // Create the path if it doesn't exist
+    if let Some(parent) = path_buf.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory: {}", e))?;
+    }
+
+    // Encrypt the content
+    let file_data = encrypt_data(&key, &path_buf, content.as_bytes())?;
+
+    // Keep the previous version before replacing it
+    save_version(&path_buf, history)?;
+
+    // Set the stamp before writing so the watcher doesn't report this change
+    app_state
+        .lock()
+        .unwrap()
+        .set_file_stamp(file_path.clone(), FileStamp::from_data(&file_data));
+
+    // Write the encrypted data to the file
+    write_atomic(&path_buf, &file_data)?;
+
+    app_state
+        .lock()
+        .unwrap()
+        .set_file_stamp(file_path, FileStamp::new(&path_buf, &file_data));
+
+    Ok(())
 }
@@ -0,0 +1,363 @@
+use crate::encryption::{decrypt_data, encrypt_data, keyring::Keyring};
+use crate::file_operations::{
+    folder_ops::{collect_encrypted_files, read_entries},
+    fs_utils::write_atomic,
+    history_ops::{history_dir, history_root, merge_history, now_millis},
+    name_ops::{display_name, item_path, unique_name},
+    note_ops::relocate_note_data,
+    watcher::{add_item, remove_item},
+};
+use crate::state::AppState;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{Emitter, State, Window};
+use zeroize::Zeroizing;
+
+/// Name of the folder with the deleted items, inside the .lockd folder
+const TRASH_FOLDER: &str = "trash";
+
+/// Name of the encrypted file that describes a deleted item, in its trash entry
+const TRASH_INFO_FILE: &str = "info.lockd";
+
+/// Name of the folder with the deleted item, in its trash entry.
+/// Entries of older versions have the item next to the info file.
+const TRASH_ITEM_FOLDER: &str = "item";
+
+/// Name of the folder with the history of the deleted notes, in their trash entry
+const TRASH_HISTORY_FOLDER: &str = "history";
+
+/// Deleted item, it is encrypted because it contains the original path
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    pub id: String,
+    pub name: String,
+    /// Path relative to the vault, with / as separator
+    pub original_path: String,
+    /// Original path as shown in the app, the names on disk can be encrypted
+    #[serde(default)]
+    pub display_path: String,
+    pub is_directory: bool,
+    pub deleted_at: u64,
+}
+
+/// Folder with the deleted items of a vault
+pub fn trash_root(vault_root: &Path) -> PathBuf {
+    vault_root.join(".lockd").join(TRASH_FOLDER)
+}
+
+/// Gets the path of an opened vault from its id
+fn vault_path(folder_id: &str, app_state: &State<Mutex<AppState>>) -> Result<PathBuf, String> {
+    let state = app_state.lock().unwrap();
+    state
+        .find_root_of(folder_id)
+        .filter(|root| root.id == folder_id && root.is_directory)
+        .map(|root| PathBuf::from(&root.path))
+        .ok_or("Folder not found".to_string())
+}
+
+/// Gets the folder of a trash entry, the id comes from the frontend so it is checked
+fn entry_dir(vault_root: &Path, entry_id: &str) -> Result<PathBuf, String> {
+    if entry_id.is_empty() || !entry_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("Invalid trash entry".to_string());
+    }
+    Ok(trash_root(vault_root).join(entry_id))
+}
+
+#[tauri::command]
+/// Moves a note or folder of an opened vault to the trash of the vault.
+/// The files are moved as they are, so they stay encrypted.
+pub fn delete_item(
+    id: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<(), String> {
+    let (root_id, vault_root, item_path) = {
+        let state = app_state.lock().unwrap();
+        let root = state
+            .find_root_of(id)
+            .filter(|root| root.is_directory && root.id != id)
+            .ok_or("Only items inside an opened folder can be deleted")?;
+        let item_path = state.get_path_from_id(id).ok_or("Item not found")?;
+        (
+            root.id.clone(),
+            PathBuf::from(&root.path),
+            PathBuf::from(item_path),
+        )
+    };
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+
+    let name = item_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or("Invalid item path")?
+        .to_string();
+    let relative_path = item_path
+        .strip_prefix(&vault_root)
+        .map_err(|_| "Invalid item path")?;
+    let original_path = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    // Decrypt the names of the item and the folders that contain it
+    let mut display_parts = Vec::new();
+    let mut path = vault_root.clone();
+    for component in relative_path.components() {
+        path.push(component);
+        display_parts.push(display_name(&key, &path));
+    }
+
+    let entry = TrashEntry {
+        id: hex::encode(rand::random::<[u8; 16]>()),
+        name: name.clone(),
+        original_path,
+        display_path: display_parts.join("/"),
+        is_directory: item_path.is_dir(),
+        deleted_at: now_millis(),
+    };
+
+    // The history of the notes is found by their path, it is moved with them
+    let notes = match entry.is_directory {
+        true => collect_encrypted_files(&item_path)?,
+        false => vec![item_path.clone()],
+    };
+
+    // Describe the item before moving it, so an entry never lacks its info
+    let entry_path = trash_root(&vault_root).join(&entry.id);
+    std::fs::create_dir_all(entry_path.join(TRASH_ITEM_FOLDER))
+        .map_err(|e| format!("Failed to create trash directory: {}", e))?;
+
+    let info = serde_json::to_vec(&entry)
+        .map(Zeroizing::new)
+        .map_err(|e| format!("Failed to serialize trash entry: {}", e))?;
+    let info_path = entry_path.join(TRASH_INFO_FILE);
+    write_atomic(&info_path, &encrypt_data(&key, &info_path, &info)?)?;
+
+    if let Err(e) = std::fs::rename(&item_path, entry_path.join(TRASH_ITEM_FOLDER).join(&name)) {
+        let _ = std::fs::remove_dir_all(&entry_path);
+        return Err(format!("Failed to move item to the trash: {}", e));
+    }
+
+    // The item is already in the trash, a history that can't be moved stays where it was
+    let history_moved = trash_history(&notes, &entry_path.join(TRASH_HISTORY_FOLDER));
+
+    remove_item(&window, &root_id, &item_path.to_string_lossy())?;
+    history_moved
+}
+
+/// Moves the history folders of deleted notes into their trash entry, they keep their names
+fn trash_history(notes: &[PathBuf], trash_history_path: &Path) -> Result<(), String> {
+    for note_path in notes {
+        let Some(dir) = history_dir(note_path).filter(|dir| dir.exists()) else {
+            continue;
+        };
+        let dir_name = dir.file_name().ok_or("Invalid note history path")?;
+
+        std::fs::create_dir_all(trash_history_path)
+            .map_err(|e| format!("Failed to create trash directory: {}", e))?;
+        std::fs::rename(&dir, trash_history_path.join(dir_name))
+            .map_err(|e| format!("Failed to move note history to the trash: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Moves the history of restored notes back to the history of the vault.
+/// They go to their original location, a note created there since keeps its versions too.
+fn restore_history(trash_history_path: &Path, vault_root: &Path) -> Result<(), String> {
+    if !trash_history_path.exists() {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(history_root(vault_root))
+        .map_err(|e| format!("Failed to create note history directory: {}", e))?;
+    for dir in read_entries(trash_history_path)? {
+        let dir_name = dir.file_name().ok_or("Invalid note history path")?;
+        merge_history(&dir, &history_root(vault_root).join(dir_name))?;
+    }
+
+    Ok(())
+}
+
+/// Reads the encrypted description of a trash entry
+fn read_entry(entry_path: &Path, app_state: &State<Mutex<AppState>>) -> Result<TrashEntry, String> {
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+
+    let info_path = entry_path.join(TRASH_INFO_FILE);
+    let file_data =
+        std::fs::read(&info_path).map_err(|e| format!("Failed to read trash entry: {}", e))?;
+    let info = Zeroizing::new(decrypt_data(&key, &info_path, &file_data)?);
+
+    serde_json::from_slice(&info).map_err(|e| format!("Invalid trash entry: {}", e))
+}
+
+#[tauri::command]
+/// Lists the deleted items of an opened vault, the most recent first.
+/// The entries that can't be read are left out and reported, so the others can be restored.
+pub fn list_trash(
+    folder_id: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<Vec<TrashEntry>, String> {
+    let trash_path = trash_root(&vault_path(folder_id, &app_state)?);
+    if !trash_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let entries = std::fs::read_dir(&trash_path)
+        .map_err(|e| format!("Failed to read trash directory: {}", e))?;
+
+    let mut items = Vec::new();
+    let mut failed = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+        if !entry.path().is_dir() {
+            continue;
+        }
+        match read_entry(&entry.path(), &app_state) {
+            Ok(item) => items.push(item),
+            Err(err) => failed.push(format!("{}: {}", entry.file_name().to_string_lossy(), err)),
+        }
+    }
+
+    if !failed.is_empty() {
+        window
+            .emit(
+                "error",
+                format!(
+                    "{} items of the trash can't be read: {}",
+                    failed.len(),
+                    failed.join(", ")
+                ),
+            )
+            .map_err(|e| format!("Failed to emit error event: {}", e))?;
+    }
+
+    items.sort_by_key(|item| std::cmp::Reverse(item.deleted_at));
+    Ok(items)
+}
+
+/// Checks a part of a path read from a trash entry, so it can't point outside of the vault
+fn check_path_part(part: &str) -> Result<(), String> {
+    if part.is_empty() || part == "." || part == ".." || part.contains(std::path::is_separator) {
+        return Err("Invalid path in trash entry".to_string());
+    }
+    Ok(())
+}
+
+/// Original path of a deleted item as shown in the app, split in its parts.
+/// Entries of older versions don't have it, their names on disk are decrypted.
+fn shown_path(key: &Keyring, vault_root: &Path, entry: &TrashEntry) -> Result<Vec<String>, String> {
+    let parts: Vec<String> = match entry.display_path.is_empty() {
+        true => {
+            let mut path = vault_root.to_path_buf();
+            entry
+                .original_path
+                .split('/')
+                .map(|part| {
+                    path.push(part);
+                    display_name(key, &path)
+                })
+                .collect()
+        }
+        false => entry.display_path.split('/').map(String::from).collect(),
+    };
+
+    for part in &parts {
+        check_path_part(part)?;
+    }
+    Ok(parts)
+}
+
+#[tauri::command]
+/// Moves a deleted item back to its original location.
+/// If the location is taken, a number is added to the name.
+pub fn restore_trash_item(
+    folder_id: &str,
+    entry_id: &str,
+    app_state: State<Mutex<AppState>>,
+    window: Window,
+) -> Result<(), String> {
+    let vault_root = vault_path(folder_id, &app_state)?;
+    let entry_path = entry_dir(&vault_root, entry_id)?;
+    let entry = read_entry(&entry_path, &app_state)?;
+
+    let key = app_state.lock().unwrap().get_encryption_key()?;
+
+    // Rebuild the path from its parts so it can't point outside of the vault
+    let mut original_target = vault_root.clone();
+    for part in entry.original_path.split('/') {
+        check_path_part(part)?;
+        original_target.push(part);
+    }
+
+    // The names on disk could have been encrypted or decrypted since the item was deleted,
+    // so the folders are found by their names in the app. The original folder could have
+    // been deleted too, it is created with the names of the vault now.
+    let shown_parts = shown_path(&key, &vault_root, &entry)?;
+    let (shown_name, parent_parts) = shown_parts
+        .split_last()
+        .ok_or("Invalid path in trash entry")?;
+    let mut parent = vault_root.clone();
+    for part in parent_parts {
+        parent = item_path(&key, &parent, part)?;
+    }
+
+    // Add a number to the name if it is taken, the names on disk can be encrypted
+    let name = unique_name(&key, &parent, shown_name)?;
+    let target = item_path(&key, &parent, &name)?;
+    std::fs::create_dir_all(&parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+
+    let mut item_source = entry_path.join(TRASH_ITEM_FOLDER).join(&entry.name);
+    if !item_source.exists() {
+        item_source = entry_path.join(&entry.name);
+    }
+    restore_history(&entry_path.join(TRASH_HISTORY_FOLDER), &vault_root)?;
+    std::fs::rename(item_source, &target).map_err(|e| format!("Failed to restore item: {}", e))?;
+    std::fs::remove_dir_all(&entry_path)
+        .map_err(|e| format!("Failed to remove trash entry: {}", e))?;
+
+    // The notes are tagged with their original location, a new name changes it
+    if target != original_target {
+        let notes = match target.is_dir() {
+            true => collect_encrypted_files(&target)?,
+            false => vec![target.clone()],
+        };
+        for note_path in notes {
+            let relative_path = note_path.strip_prefix(&target).unwrap_or(Path::new(""));
+            let original_path = match relative_path.as_os_str().is_empty() {
+                true => original_target.clone(),
+                false => original_target.join(relative_path),
+            };
+            relocate_note_data(&original_path, &note_path, &app_state)?;
+        }
+    }
+
+    add_item(&window, folder_id, &vault_root, &target)
+}
+
+#[tauri::command]
+/// Permanently deletes an item from the trash
+pub fn purge_trash_item(
+    folder_id: &str,
+    entry_id: &str,
+    app_state: State<Mutex<AppState>>,
+) -> Result<(), String> {
+    let vault_root = vault_path(folder_id, &app_state)?;
+    let entry_path = entry_dir(&vault_root, entry_id)?;
+
+    std::fs::remove_dir_all(&entry_path).map_err(|e| format!("Failed to delete trash entry: {}", e))
+}
+
+#[tauri::command]
+/// Permanently deletes every item in the trash of a vault
+pub fn empty_trash(folder_id: &str, app_state: State<Mutex<AppState>>) -> Result<(), String> {
+    let trash_path = trash_root(&vault_path(folder_id, &app_state)?);
+    if !trash_path.exists() {
+        return Ok(());
+    }
+
+    std::fs::remove_dir_all(&trash_path).map_err(|e| format!("Failed to empty trash: {}", e))
+}
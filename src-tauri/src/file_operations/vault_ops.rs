@@ -0,0 +1,562 @@
+use crate::encryption::{
+    check_file_key,
+    header::{Cipher, KdfParams},
+    kdf::{derive_master_key, login_secret},
+    keyfile::{VaultKeyfile, VAULT_ID_LEN},
+    keyring::{Keyring, PASSWORD_NEEDED_ERROR},
+    options::EncryptionOptions,
+    read_file_vault_id,
+    recovery::{derive_recovery_key, generate_recovery_code, parse_recovery_code},
+    reencrypt_stream_to,
+    salts::user_salt,
+    secret::SecretKey,
+};
+use crate::file_operations::{
+    drag_drop::can_open_folder,
+    folder_ops::{collect_encrypted_files, open_folder},
+    fs_utils::{write_atomic, write_atomic_with, FileStamp},
+    history_ops::history_root,
+    trash_ops::trash_root,
+};
+use crate::state::AppState;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, State, Window};
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
+
+/// Name of the keyfile of a vault, inside the .lockd folder
+const KEYFILE_NAME: &str = "vault.json";
+
+/// Name of the keyfile of a vault being created, until its files are moved to the data key
+const PENDING_KEYFILE_NAME: &str = "vault.json.pending";
+
+/// Result of the unlock of a vault
+pub struct UnlockedVault {
+    /// Recovery code of a new vault, it is the only time it is available
+    pub recovery_code: Option<String>,
+    /// Errors of the files that couldn't be moved to the data key,
+    /// they still need the password and the recovery code doesn't open them
+    pub unmigrated: Vec<String>,
+}
+
+/// Progress of a password change, there is an event for each file
+#[derive(Clone, serde::Serialize)]
+struct PasswordChangeProgress {
+    done: usize,
+    total: usize,
+    path: String,
+}
+
+/// Path of the keyfile of a vault
+pub fn keyfile_path(vault_path: &Path) -> PathBuf {
+    vault_path.join(".lockd").join(KEYFILE_NAME)
+}
+
+/// Finds the innermost vault that contains the path
+pub fn find_vault_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .find(|dir| can_open_folder(&dir.to_path_buf()))
+        .map(Path::to_path_buf)
+}
+
+/// Checks if a folder is a vault, or was being made one when it was interrupted
+pub fn is_vault(folder_path: &Path) -> bool {
+    keyfile_path(folder_path).exists() || pending_keyfile_path(folder_path).exists()
+}
+
+/// Path of the keyfile of a vault being created
+fn pending_keyfile_path(vault_path: &Path) -> PathBuf {
+    vault_path.join(".lockd").join(PENDING_KEYFILE_NAME)
+}
+
+/// Reads the keyfile of a vault, if it has one
+pub fn load_keyfile(vault_path: &Path) -> Result<Option<VaultKeyfile>, String> {
+    read_keyfile(&keyfile_path(vault_path))
+}
+
+fn read_keyfile(path: &Path) -> Result<Option<VaultKeyfile>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read vault keyfile: {}", e))?;
+    VaultKeyfile::from_json(&content).map(Some)
+}
+
+/// Writes the keyfile of a vault atomically, so it can't be lost halfway through
+pub fn save_keyfile(vault_path: &Path, keyfile: &VaultKeyfile) -> Result<(), String> {
+    write_atomic(&keyfile_path(vault_path), keyfile.to_json()?.as_bytes())
+}
+
+/// Encrypted files of a vault, with the history of the notes and the trash.
+/// The files of nested vaults belong to them and are left out.
+fn vault_files(vault_path: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = collect_encrypted_files(vault_path)?;
+    for hidden_path in [history_root(vault_path), trash_root(vault_path)] {
+        if hidden_path.exists() {
+            files.extend(collect_encrypted_files(&hidden_path)?);
+        }
+    }
+    files.retain(|file| find_vault_root(file).as_deref() == Some(vault_path));
+
+    Ok(files)
+}
+
+/// Unwraps the data key of a vault with the password of the session and keeps it in the keyring.
+/// Folders without a keyfile become vaults, see `create_vault`.
+/// The files still encrypted with a password are moved to the data key,
+/// so the recovery code opens all of them.
+pub fn unlock_vault(vault_path: &Path, key: &Keyring) -> Result<UnlockedVault, String> {
+    let (mut keyfile, data_key, recovery_code) = match load_keyfile(vault_path)? {
+        Some(mut keyfile) => {
+            let wrapping_key = key.master_key(&keyfile.salt()?, &keyfile.kdf_params)?;
+            let data_key = keyfile.unwrap_key(&wrapping_key)?;
+
+            // Wrap it again with the master key of the session, so the vault opens without
+            // another derivation next time. Keyfiles of older versions also get a verifier.
+            let (salt, kdf_params, master_key) = key.current_master()?;
+            if keyfile.salt()? != salt
+                || keyfile.kdf_params != kdf_params
+                || keyfile.verifier.is_none()
+            {
+                keyfile.rewrap(&salt, kdf_params, &master_key, &data_key)?;
+                save_keyfile(vault_path, &keyfile)?;
+            }
+            (keyfile, data_key, None)
+        }
+        None => {
+            let (keyfile, data_key, recovery_code) = create_vault(vault_path, key)?;
+            (keyfile, data_key, Some(recovery_code))
+        }
+    };
+
+    key.add_vault(
+        keyfile.id()?,
+        vault_path.to_path_buf(),
+        data_key,
+        keyfile.encrypt_names,
+        keyfile.cipher,
+    )?;
+
+    // The files that fail are tried again the next time the vault is opened
+    let mut unmigrated = Vec::new();
+    if !keyfile.migrated {
+        unmigrated = migrate_files(vault_path, key)?;
+        keyfile.migrated = unmigrated.is_empty();
+        save_keyfile(vault_path, &keyfile)?;
+    }
+
+    // The vault is created once its keyfile is in place
+    if recovery_code.is_some() {
+        std::fs::remove_file(pending_keyfile_path(vault_path))
+            .map_err(|e| format!("Failed to remove pending vault keyfile: {}", e))?;
+    }
+
+    Ok(UnlockedVault {
+        recovery_code,
+        unmigrated,
+    })
+}
+
+/// Unlocks a vault and tells the user what happened to it:
+/// the recovery code of a new vault and the files still encrypted with a password.
+pub fn open_vault(vault_path: &Path, window: &Window, key: &Keyring) -> Result<(), String> {
+    let vault_name = vault_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("Unknown Folder");
+
+    let unlocked = unlock_vault(vault_path, key)?;
+    if let Some(recovery_code) = &unlocked.recovery_code {
+        show_recovery_code(window, vault_path, recovery_code, unlocked.unmigrated.len());
+    }
+    if !unlocked.unmigrated.is_empty() {
+        window
+            .emit(
+                "error",
+                format!(
+                    "{} files of {} are still encrypted with a password: {}",
+                    unlocked.unmigrated.len(),
+                    vault_name,
+                    unlocked.unmigrated.join(", ")
+                ),
+            )
+            .map_err(|e| format!("Failed to emit error event: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Unlocks the vault that contains a note opened on its own, so its key is there to decrypt it.
+/// Folders that aren't vaults yet are left as they are, their notes are encrypted with a password.
+/// The vault stays unlocked until the session is locked or the vault is closed.
+pub fn unlock_vault_of(file_path: &Path, window: &Window, key: &Keyring) -> Result<(), String> {
+    let Some(vault_path) = find_vault_root(file_path) else {
+        return Ok(());
+    };
+    let Some(keyfile) = load_keyfile(&vault_path)? else {
+        return Ok(());
+    };
+
+    if key.vault_key(&keyfile.id()?).is_none() {
+        open_vault(&vault_path, window, key)?;
+    }
+    Ok(())
+}
+
+/// Makes a folder opened for the first time a vault, with a new random data key and a recovery code.
+/// If the folder already has notes, one of them must decrypt with the keys of the session first,
+/// so a mistyped password doesn't become the password of the vault.
+/// The keyfile is kept aside until the files are moved to the data key,
+/// an interrupted creation continues from it the next time the folder is opened.
+fn create_vault(
+    vault_path: &Path,
+    key: &Keyring,
+) -> Result<(VaultKeyfile, SecretKey, String), String> {
+    let pending_path = pending_keyfile_path(vault_path);
+
+    let (mut keyfile, data_key) = match read_keyfile(&pending_path)? {
+        Some(keyfile) => {
+            let wrapping_key = key.master_key(&keyfile.salt()?, &keyfile.kdf_params)?;
+            let data_key = keyfile.unwrap_key(&wrapping_key)?;
+            (keyfile, data_key)
+        }
+        None => {
+            check_password(vault_path, key)?;
+
+            // Wrap it with the master key of the session, so it opens without another derivation
+            let id: [u8; VAULT_ID_LEN] = rand::random();
+            let data_key = SecretKey::random();
+            let (salt, kdf_params, wrapping_key) = key.current_master()?;
+            let keyfile = VaultKeyfile::new(&id, &salt, kdf_params, &wrapping_key, &data_key)?;
+            (keyfile, data_key)
+        }
+    };
+
+    // The recovery code can also unwrap the data key if the password is forgotten.
+    // It is only shown once the vault is created, so an interrupted creation gets a new one.
+    let (code, printable_code) = generate_recovery_code();
+    let recovery_key = derive_recovery_key(&code, &keyfile.id()?)?;
+    keyfile.set_recovery_key(&recovery_key, &data_key)?;
+
+    write_atomic(&pending_path, keyfile.to_json()?.as_bytes())?;
+
+    Ok((keyfile, data_key, printable_code))
+}
+
+/// Checks the keys of the session against the notes already in a folder.
+/// One file that decrypts is enough, the others are checked when they are moved to the data key.
+/// A folder without notes has nothing to check against, that case isn't covered:
+/// its vault takes the password of the session, which was typed twice at login.
+fn check_password(vault_path: &Path, key: &Keyring) -> Result<(), String> {
+    let mut has_notes = false;
+    let mut needs_password = false;
+
+    for file_path in vault_files(vault_path)? {
+        let is_empty = std::fs::metadata(&file_path)
+            .map(|metadata| metadata.len() == 0)
+            .map_err(|e| format!("Failed to read file {}: {}", file_path.display(), e))?;
+        if is_empty {
+            continue;
+        }
+        has_notes = true;
+
+        // Large files encrypted in chunks are only checked with their first chunk
+        match check_file_key(key, &file_path) {
+            Ok(()) => return Ok(()),
+            Err(err) if err == PASSWORD_NEEDED_ERROR => needs_password = true,
+            Err(_) => {}
+        }
+    }
+
+    match (has_notes, needs_password) {
+        (false, _) => Ok(()),
+        // The notes may open once the keys of their sessions are derived
+        (true, true) => Err(PASSWORD_NEEDED_ERROR.to_string()),
+        (true, false) => Err(
+            "The notes of this folder don't open with this username and password, \
+             check them before opening the folder"
+                .to_string(),
+        ),
+    }
+}
+
+/// Moves the files of a vault that are still encrypted with a password to its data key.
+/// Returns the errors of the files that failed, they keep their key.
+fn migrate_files(vault_path: &Path, key: &Keyring) -> Result<Vec<String>, String> {
+    let mut failed = Vec::new();
+    for file_path in vault_files(vault_path)? {
+        if let Err(e) = reencrypt_file(&file_path, key) {
+            failed.push(e);
+        }
+    }
+    Ok(failed)
+}
+
+/// Shows the recovery code of a new vault, it is the only time it is available.
+/// It only opens the files that were moved to the data key, the others are counted in the message.
+pub fn show_recovery_code(
+    window: &Window,
+    vault_path: &Path,
+    recovery_code: &str,
+    unmigrated: usize,
+) {
+    let vault_name = vault_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("vault");
+
+    let warning = match unmigrated {
+        0 => String::new(),
+        _ => format!(
+            "\n\n{} files of the vault are still encrypted with a password and the code \
+             doesn't open them. They are moved to the key of the vault when it is opened \
+             again and they can be decrypted.",
+            unmigrated
+        ),
+    };
+
+    window
+        .dialog()
+        .message(format!(
+            "Write down or print the recovery code of {}, it unlocks the vault \
+             if you forget your password:\n\n{}\n\nIt won't be shown again.{}",
+            vault_name, recovery_code, warning
+        ))
+        .title("Recovery code")
+        .kind(MessageDialogKind::Warning)
+        .show(|_| {});
+}
+
+#[tauri::command]
+/// Logs in with the recovery code of a vault instead of the old password.
+/// The user picks the vault, its data key is wrapped with the new username and password,
+/// which become the keys of the session, and the vault is opened.
+pub fn recover_vault(
+    recovery_code: &str,
+    username: &str,
+    password: &str,
+    kdf_params: Option<KdfParams>,
+    app_state: State<Mutex<AppState>>,
+    app_handle: AppHandle,
+    window: Window,
+) -> Result<bool, String> {
+    // Check the code before showing the dialog
+    let code = parse_recovery_code(recovery_code)?;
+
+    let folder_path = app_handle
+        .dialog()
+        .file()
+        .set_title("Select the vault to recover")
+        .blocking_pick_folder();
+
+    // It is ok to cancel the dialog
+    let Some(folder_path) = folder_path else {
+        return Ok(false);
+    };
+    let folder_path = folder_path
+        .into_path()
+        .map_err(|e| format!("Invalid folder path: {}", e))?;
+
+    if !can_open_folder(&folder_path) {
+        return Err("The folder is not a vault".to_string());
+    }
+
+    let mut keyfile = load_keyfile(&folder_path)?.ok_or("The vault has no keyfile")?;
+    let vault_id = keyfile.id()?;
+    let recovery_key = derive_recovery_key(&code, &vault_id)?;
+    let data_key = keyfile.unwrap_recovery_key(&recovery_key)?;
+
+    // Log in with the new password and wrap the data key with it
+    let key = Keyring::new(
+        username,
+        password,
+        user_salt(&app_handle, username)?,
+        kdf_params.unwrap_or_default(),
+    )?;
+    key.set_options(EncryptionOptions::load(&app_handle)?);
+    let (salt, kdf_params, wrapping_key) = key.current_master()?;
+
+    keyfile.rewrap(&salt, kdf_params, &wrapping_key, &data_key)?;
+    save_keyfile(&folder_path, &keyfile)?;
+
+    key.add_vault(
+        vault_id,
+        folder_path.clone(),
+        data_key,
+        keyfile.encrypt_names,
+        keyfile.cipher,
+    )?;
+    app_state.lock().unwrap().set_key(key);
+
+    open_folder(&folder_path, &window, app_state)?;
+
+    Ok(true)
+}
+
+#[tauri::command]
+/// Returns the cipher of the new files of an opened vault
+pub fn get_vault_cipher(
+    folder_id: &str,
+    app_state: State<Mutex<AppState>>,
+) -> Result<Cipher, String> {
+    let folder_path = app_state
+        .lock()
+        .unwrap()
+        .get_path_from_id(folder_id)
+        .ok_or("Folder not found")?;
+
+    Ok(load_keyfile(Path::new(&folder_path))?
+        .map(|keyfile| keyfile.cipher)
+        .unwrap_or_default())
+}
+
+#[tauri::command]
+/// Changes the cipher of the new files of an opened vault.
+/// The existing files keep their cipher until they are saved again, the header of every file
+/// tells which one it uses, so both can be read in the same vault.
+pub fn set_vault_cipher(
+    folder_id: &str,
+    cipher: Cipher,
+    app_state: State<Mutex<AppState>>,
+) -> Result<(), String> {
+    let (key, folder_path) = {
+        let state = app_state.lock().unwrap();
+        let root = state
+            .find_root_of(folder_id)
+            .filter(|root| root.id == folder_id && root.is_directory)
+            .ok_or("Folder not found")?;
+        (state.get_encryption_key()?, PathBuf::from(&root.path))
+    };
+
+    let mut keyfile = load_keyfile(&folder_path)?.ok_or("The folder is not an unlocked vault")?;
+    keyfile.cipher = cipher;
+    save_keyfile(&folder_path, &keyfile)?;
+
+    key.set_vault_cipher(&folder_path, cipher);
+
+    Ok(())
+}
+
+#[tauri::command]
+/// Changes the username and password of an opened vault by rewrapping its data key.
+/// Files still encrypted with the password itself are re-encrypted with the data key first,
+/// if it is interrupted calling it again continues with the remaining files.
+pub async fn change_password(
+    folder_id: String,
+    username: String,
+    password: String,
+    kdf_params: Option<KdfParams>,
+    window: Window,
+) -> Result<(), String> {
+    // Run it outside of the async runtime, it can take a long time
+    tauri::async_runtime::spawn_blocking(move || {
+        rewrap_vault(&folder_id, &username, &password, kdf_params, &window)
+    })
+    .await
+    .map_err(|e| format!("Password change failed: {}", e))?
+}
+
+/// Moves every file of a vault to its data key and wraps the data key with the new password
+fn rewrap_vault(
+    folder_id: &str,
+    username: &str,
+    password: &str,
+    kdf_params: Option<KdfParams>,
+    window: &Window,
+) -> Result<(), String> {
+    let app_state = window.state::<Mutex<AppState>>();
+
+    let (key, folder_path) = {
+        let state = app_state.lock().unwrap();
+        let key = state.get_encryption_key()?;
+        let path = state
+            .get_path_from_id(folder_id)
+            .ok_or("Folder not found")?;
+        (key, PathBuf::from(path))
+    };
+
+    if !can_open_folder(&folder_path) {
+        return Err("The folder is not a vault".to_string());
+    }
+
+    let mut keyfile = load_keyfile(&folder_path)?.ok_or("The vault has no keyfile")?;
+    let vault_id = keyfile.id()?;
+    let data_key = key
+        .vault_key(&vault_id)
+        .ok_or("Open the vault before changing its password")?;
+
+    // The history of the notes and the trash are encrypted too
+    let files = vault_files(&folder_path)?;
+    let total = files.len();
+
+    for (index, file_path) in files.iter().enumerate() {
+        // Opened notes must not look changed outside of the app afterwards,
+        // they are small enough to be read again
+        if reencrypt_file(file_path, &key)? {
+            let path = file_path.to_string_lossy().to_string();
+            let tracked = app_state.lock().unwrap().get_file_stamp(&path).is_some();
+            if tracked {
+                let file_data = std::fs::read(file_path)
+                    .map_err(|e| format!("Failed to read file {}: {}", file_path.display(), e))?;
+                let stamp = FileStamp::new(file_path, &file_data);
+                app_state.lock().unwrap().set_file_stamp(path, stamp);
+            }
+        }
+
+        let relative_path = file_path
+            .strip_prefix(&folder_path)
+            .unwrap_or(file_path)
+            .to_string_lossy()
+            .to_string();
+
+        window
+            .emit(
+                "password-change-progress",
+                PasswordChangeProgress {
+                    done: index + 1,
+                    total,
+                    path: relative_path,
+                },
+            )
+            .map_err(|e| format!("Failed to emit event: {}", e))?;
+    }
+
+    // Only the keyfile depends on the password now, it uses the salt of the new user
+    // so the vault opens with the key derived when they log in
+    let kdf_params = kdf_params.unwrap_or(keyfile.kdf_params);
+    let salt = user_salt(window.app_handle(), username)?;
+    let wrapping_key = derive_master_key(&login_secret(username, password), &salt, &kdf_params)?;
+
+    keyfile.rewrap(&salt, kdf_params, &wrapping_key, &data_key)?;
+    save_keyfile(&folder_path, &keyfile)?;
+
+    window
+        .emit("password-changed", folder_id)
+        .map_err(|e| format!("Failed to emit event: {}", e))?;
+
+    Ok(())
+}
+
+/// Re-encrypts a file with the data key of its vault, replacing it atomically.
+/// Files encrypted in chunks are re-encrypted a chunk at a time, so large files fit in memory.
+/// Returns false for files already encrypted with it, they are left untouched.
+fn reencrypt_file(file_path: &Path, key: &Keyring) -> Result<bool, String> {
+    // Files of nested vaults belong to the innermost one
+    let vault_id = key.vault_for_path(file_path).map(|(id, _, _)| id);
+    if vault_id.is_some() && read_file_vault_id(file_path)? == vault_id {
+        return Ok(false);
+    }
+
+    // The copies of notes in the history keep the identity of their note
+    let mut source = std::fs::File::open(file_path)
+        .map_err(|e| format!("Failed to read file {}: {}", file_path.display(), e))?;
+    write_atomic_with(file_path, |output| {
+        reencrypt_stream_to(key, file_path, &mut source, output)
+    })
+    .map_err(|e| format!("{}: {}", file_path.display(), e))?;
+
+    Ok(true)
+}
@@ -0,0 +1,199 @@
+use crate::file_operations::folder_ops::{is_hidden, scan_item};
+use crate::state::{AppState, FileSystemItemFrontend};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{Emitter, Manager, Window};
+
+/// An item appeared inside an opened folder
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemAdded {
+    pub root_id: String,
+    /// Id of the directory that contains the item, the root id for the top level
+    pub parent_item_id: String,
+    pub item: FileSystemItemFrontend,
+}
+
+/// An item inside an opened folder was removed or changed
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemEvent {
+    pub root_id: String,
+    pub id: String,
+}
+
+/// Starts watching an opened folder, the changes on disk update its tree in the app state.
+/// The watcher stops when it is dropped.
+pub fn watch_folder(
+    root_id: String,
+    root_path: PathBuf,
+    window: &Window,
+) -> Result<RecommendedWatcher, String> {
+    let handler_window = window.clone();
+    let handler_root = root_path.clone();
+
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<Event>| {
+        let Ok(event) = result else {
+            return;
+        };
+        // Reading a file doesn't change the tree
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+
+        for path in event.paths {
+            if let Err(err) = sync_path(&handler_window, &root_id, &handler_root, &path) {
+                let _ = handler_window.emit("error", err);
+            }
+        }
+    })
+    .map_err(|e| format!("Failed to create folder watcher: {}", e))?;
+
+    watcher
+        .watch(&root_path, RecursiveMode::Recursive)
+        .map_err(|e| format!("Failed to watch folder {}: {}", root_path.display(), e))?;
+
+    Ok(watcher)
+}
+
+/// Checks if a change should be ignored, the contents of hidden folders like .lockd
+/// and hidden files like the temporary files of the atomic writes are not shown
+fn is_ignored(root_path: &Path, path: &Path) -> bool {
+    match path.strip_prefix(root_path) {
+        Ok(relative_path) => relative_path
+            .components()
+            .any(|component| is_hidden(Path::new(component.as_os_str()))),
+        Err(_) => true,
+    }
+}
+
+/// Updates the tree of an opened folder to match a path on disk and emits the change
+fn sync_path(window: &Window, root_id: &str, root_path: &Path, path: &Path) -> Result<(), String> {
+    if path == root_path || is_ignored(root_path, path) {
+        return Ok(());
+    }
+
+    let app_state = window.state::<Mutex<AppState>>();
+    let path_str = path
+        .to_str()
+        .ok_or("Invalid file path encoding")?
+        .to_string();
+
+    let existing = app_state
+        .lock()
+        .unwrap()
+        .find_child_by_path(root_id, &path_str)
+        .map(|item| (item.id.clone(), item.is_directory));
+
+    match existing {
+        // A file or folder was replaced by the other kind
+        Some((_, is_directory)) if path.exists() && path.is_dir() != is_directory => {
+            remove_item(window, root_id, &path_str)?;
+            add_item(window, root_id, root_path, path)
+        }
+        Some((id, is_directory)) if path.exists() => {
+            if is_directory {
+                return Ok(());
+            }
+
+            // The app writes notes itself, only report the changes made by others
+            let unchanged = app_state
+                .lock()
+                .unwrap()
+                .get_file_stamp(&path_str)
+                .is_some_and(|stamp| stamp.matches_file(path));
+            if unchanged {
+                return Ok(());
+            }
+
+            window
+                .emit(
+                    "item-changed",
+                    ItemEvent {
+                        root_id: root_id.to_string(),
+                        id,
+                    },
+                )
+                .map_err(|e| format!("Failed to emit item-changed event: {}", e))
+        }
+        Some(_) => remove_item(window, root_id, &path_str),
+        None if path.exists() => add_item(window, root_id, root_path, path),
+        None => Ok(()),
+    }
+}
+
+/// Adds a new path to the tree, and its parent directories if they are missing
+pub fn add_item(
+    window: &Window,
+    root_id: &str,
+    root_path: &Path,
+    path: &Path,
+) -> Result<(), String> {
+    let app_state = window.state::<Mutex<AppState>>();
+    let parent_path = path.parent().ok_or("Invalid file path")?;
+    let parent_path_str = parent_path.to_string_lossy().to_string();
+
+    // The event of a new directory can arrive after the events of its contents
+    if parent_path != root_path
+        && app_state
+            .lock()
+            .unwrap()
+            .find_child_by_path(root_id, &parent_path_str)
+            .is_none()
+    {
+        return sync_path(window, root_id, root_path, parent_path);
+    }
+
+    // Scan without holding the lock, it is needed to register the ids
+    let item = scan_item(&path.to_path_buf(), &app_state, &root_id.to_string());
+
+    let (parent_item_id, frontend_item) = {
+        let mut state = app_state.lock().unwrap();
+
+        // Another event could have added it while scanning
+        if state.find_child_by_path(root_id, &item.path).is_some() {
+            return Ok(());
+        }
+
+        let frontend_item = state.to_frontend_item(&item);
+        let parent_item_id = state
+            .insert_child(root_id, &parent_path_str, item)
+            .ok_or("Parent folder not found")?;
+        (parent_item_id, frontend_item)
+    };
+
+    window
+        .emit(
+            "item-added",
+            ItemAdded {
+                root_id: root_id.to_string(),
+                parent_item_id,
+                item: frontend_item,
+            },
+        )
+        .map_err(|e| format!("Failed to emit item-added event: {}", e))
+}
+
+/// Removes a path that no longer exists from the tree
+pub fn remove_item(window: &Window, root_id: &str, path: &str) -> Result<(), String> {
+    let removed = window
+        .state::<Mutex<AppState>>()
+        .lock()
+        .unwrap()
+        .remove_child(root_id, path);
+
+    let Some(removed) = removed else {
+        return Ok(());
+    };
+
+    window
+        .emit(
+            "item-removed",
+            ItemEvent {
+                root_id: root_id.to_string(),
+                id: removed.id,
+            },
+        )
+        .map_err(|e| format!("Failed to emit item-removed event: {}", e))
+}
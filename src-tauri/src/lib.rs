@@ -3,18 +3,30 @@ use tauri::{Emitter, Manager, WindowEvent};
 
 mod app_ops;
 mod encryption;
+mod error;
 mod file_operations;
+mod lock_ops;
 mod state;
 
 use crate::file_operations::{
     commands::{
-        close_item, get_initial_config, get_opened_items, open_note_from_id, rename_note,
-        save_initial_config, save_note, save_note_as, save_note_copy,
+        accept_note_location, close_item, get_initial_config, get_opened_items, open_note_from_id,
+        rename_note, save_initial_config, save_note, save_note_as, save_note_copy,
     },
+    conflict_ops::{overwrite_note, reload_note, save_conflict_copy},
     drag_drop::drop_handler,
+    history_ops::{
+        diff_note_version, list_note_versions, preview_note_version, restore_note_version,
+    },
+    item_ops::{create_folder, create_note, move_item, rename_folder},
+    job_ops::cancel_folder_job,
+    name_ops::{get_name_encryption, set_name_encryption},
+    trash_ops::{delete_item, empty_trash, list_trash, purge_trash_item, restore_trash_item},
+    vault_ops::{change_password, get_vault_cipher, recover_vault, set_vault_cipher},
 };
 use app_ops::reset_app;
-use encryption::derive_encryption_key;
+use encryption::{calibrate_kdf, derive_encryption_key, derive_missing_keys};
+use lock_ops::{is_locked, lock_app, record_activity, unlock_app, watch_idle};
 use state::AppState;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -32,19 +44,50 @@ pub fn run() {
         })
         .setup(|app| {
             app.manage(Mutex::new(AppState::default()));
+            watch_idle(app.handle().clone());
             Ok(())
         })
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             derive_encryption_key,
+            derive_missing_keys,
+            calibrate_kdf,
+            change_password,
+            recover_vault,
+            get_vault_cipher,
+            set_vault_cipher,
             save_note,
             save_note_as,
             save_note_copy,
+            overwrite_note,
+            reload_note,
+            save_conflict_copy,
             rename_note,
+            create_note,
+            create_folder,
+            move_item,
+            rename_folder,
+            cancel_folder_job,
+            get_name_encryption,
+            set_name_encryption,
+            delete_item,
+            list_trash,
+            restore_trash_item,
+            purge_trash_item,
+            empty_trash,
+            list_note_versions,
+            preview_note_version,
+            diff_note_version,
+            restore_note_version,
             get_opened_items,
             close_item,
             open_note_from_id,
+            accept_note_location,
             reset_app,
+            record_activity,
+            lock_app,
+            is_locked,
+            unlock_app,
             get_initial_config,
             save_initial_config,
         ])
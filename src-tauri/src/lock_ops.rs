@@ -0,0 +1,134 @@
+use crate::encryption::keyring::Keyring;
+use crate::encryption::options::EncryptionOptions;
+use crate::file_operations::commands::read_config;
+use crate::file_operations::drag_drop::open_from_path;
+use crate::state::{AppState, IdleCheck};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State, Window};
+
+/// How often the idle time is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Auto-lock option of the security section of the app config
+#[derive(Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct LockOptions {
+    /// Minutes without activity before the app locks itself, 0 to never lock
+    auto_lock_minutes: u64,
+}
+
+/// Config file with only the section that is read here, the frontend saves the rest
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct LockConfig {
+    security: LockOptions,
+}
+
+/// Reads the idle time after which the app locks itself from the content of the app config,
+/// None if it never locks
+pub fn idle_timeout_from_config(config: &str) -> Option<Duration> {
+    let minutes = serde_json::from_str::<LockConfig>(config)
+        .unwrap_or_default()
+        .security
+        .auto_lock_minutes;
+
+    (minutes > 0).then(|| Duration::from_secs(minutes * 60))
+}
+
+/// Reads the idle time after which the app locks itself from the app config file
+pub fn load_idle_timeout(app_handle: &AppHandle) -> Result<Option<Duration>, String> {
+    Ok(idle_timeout_from_config(&read_config(app_handle)?))
+}
+
+/// Checks the idle time in the background and locks the app once it is over.
+/// The frontend is asked first so it can save the open note.
+pub fn watch_idle(app_handle: AppHandle) {
+    thread::spawn(move || loop {
+        thread::sleep(CHECK_INTERVAL);
+
+        let checked = app_handle
+            .state::<Mutex<AppState>>()
+            .lock()
+            .unwrap()
+            .check_idle();
+
+        let result = match checked {
+            Ok(IdleCheck::Active) => Ok(()),
+            Ok(IdleCheck::RequestLock) => app_handle
+                .emit("lock-requested", ())
+                .map_err(|e| format!("Failed to emit lock-requested event: {}", e)),
+            Ok(IdleCheck::Locked) => emit_locked(&app_handle),
+            Err(e) => Err(format!("Failed to lock the app: {}", e)),
+        };
+        if let Err(e) = result {
+            let _ = app_handle.emit("error", e);
+        }
+    });
+}
+
+/// Tells the frontend to go back to the login screen
+fn emit_locked(app_handle: &AppHandle) -> Result<(), String> {
+    app_handle
+        .emit("locked", ())
+        .map_err(|e| format!("Failed to emit locked event: {}", e))
+}
+
+// Locks the app after the frontend saved the open note, unless it was used again meanwhile.
+#[tauri::command]
+pub fn lock_app(app_state: State<Mutex<AppState>>, app_handle: AppHandle) -> Result<(), String> {
+    let locked = app_state.lock().unwrap().lock_if_requested()?;
+    if locked {
+        emit_locked(&app_handle)?;
+    }
+    Ok(())
+}
+
+// Keeps the app unlocked while the user is using it.
+#[tauri::command]
+pub fn record_activity(app_state: State<Mutex<AppState>>) {
+    app_state.lock().unwrap().record_activity();
+}
+
+// Tells the login screen to unlock the previous session instead of starting a new one.
+#[tauri::command]
+pub fn is_locked(app_state: State<Mutex<AppState>>) -> bool {
+    app_state.lock().unwrap().is_locked()
+}
+
+// Unlocks the app after it locked itself and reopens the items that were opened.
+#[tauri::command]
+pub fn unlock_app(
+    username: &str,
+    password: &str,
+    window: Window,
+    app_state: State<Mutex<AppState>>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    let (tag, master_salt, master_params) = app_state
+        .lock()
+        .unwrap()
+        .locked_master()
+        .ok_or("The app is not locked")?;
+
+    // Derive the keys before locking the state, Argon2 takes a while.
+    // The salt of the session is used, a mistyped username doesn't get a new one.
+    let keyring = Keyring::new(username, password, master_salt, master_params)?;
+    if !keyring.check_session_tag(&tag) {
+        return Err("Wrong username or password".to_string());
+    }
+    keyring.set_options(EncryptionOptions::load(&app_handle)?);
+
+    let paths = app_state.lock().unwrap().unlock(keyring);
+
+    // An item that can't be opened anymore doesn't stop the others
+    for path in paths {
+        if let Err(e) = open_from_path(&PathBuf::from(&path), &window) {
+            let _ = window.emit("error", format!("Failed to reopen {}: {}", path, e));
+        }
+    }
+
+    Ok(())
+}
@@ -1,9 +1,55 @@
+use crate::encryption::header::KdfParams;
+use crate::encryption::kdf::SALT_LEN;
+use crate::encryption::keyring::Keyring;
+use crate::file_operations::fs_utils::FileStamp;
+use notify::RecommendedWatcher;
+use std::cmp::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Time the frontend has to save the open note before the app locks itself anyway
+const LOCK_GRACE: Duration = Duration::from_secs(30);
+
 #[derive(Default)]
 pub struct AppState {
-    key: Option<[u8; 32]>,
+    key: Option<Arc<Keyring>>,
     opened_items: Vec<FileSystemItem>,
     id_to_path_map: std::collections::HashMap<String, String>,
     next_id: u32,
+    file_stamps: std::collections::HashMap<String, FileStamp>,
+    watchers: std::collections::HashMap<String, RecommendedWatcher>,
+    /// Cancel flags of the background jobs that are running
+    jobs: std::collections::HashMap<String, Arc<AtomicBool>>,
+    /// Idle time after which the app locks itself, None if it never does
+    idle_timeout: Option<Duration>,
+    last_activity: Option<Instant>,
+    /// When the frontend was asked to save the open note and lock the app
+    lock_requested: Option<Instant>,
+    /// Session waiting for the password after the app locked itself
+    locked: Option<LockedSession>,
+}
+
+/// What the idle watcher has to do after checking the idle time
+pub enum IdleCheck {
+    /// The app is in use, or it can't be locked yet
+    Active,
+    /// The app is idle, the frontend saves the open note and then locks it
+    RequestLock,
+    /// The frontend didn't lock the app in time, so it was locked
+    Locked,
+}
+
+/// What is kept of a session while the app is locked
+pub struct LockedSession {
+    /// Checks the password at unlock, see `Keyring::session_tag`
+    pub tag: [u8; 32],
+    /// The key is derived again with the salt and parameters used at login,
+    /// the settings can change during the session
+    pub master_salt: [u8; SALT_LEN],
+    pub master_params: KdfParams,
+    /// Paths of the opened items, they are opened again once unlocked
+    pub paths: Vec<String>,
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -31,14 +77,120 @@ pub struct FileSystemItem {
 
 impl AppState {
     pub fn reset(&mut self) {
+        // The keys are wiped when the keyring is dropped, once the jobs using it stop too
         self.key = None;
         self.opened_items.clear();
         self.id_to_path_map.clear();
         self.next_id = 0;
+        self.file_stamps.clear();
+        self.watchers.clear();
+
+        // The jobs stop with the session that started them
+        for cancelled in self.jobs.values() {
+            cancelled.store(true, AtomicOrdering::Relaxed);
+        }
+        self.jobs.clear();
+
+        self.last_activity = None;
+        self.lock_requested = None;
+        self.locked = None;
+    }
+
+    pub fn set_key(&mut self, key: Keyring) {
+        self.key = Some(Arc::new(key));
+        self.locked = None;
+        self.record_activity();
+    }
+
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    pub fn record_activity(&mut self) {
+        self.last_activity = Some(Instant::now());
+        self.lock_requested = None;
+    }
+
+    /// Checks if the app has been idle for too long. The frontend is asked first to save
+    /// the open note and lock the app, it is locked here if it doesn't in time.
+    /// The background jobs use the key, so the lock waits for them to finish.
+    pub fn check_idle(&mut self) -> Result<IdleCheck, String> {
+        let (Some(_), Some(idle_timeout), Some(last_activity)) =
+            (&self.key, self.idle_timeout, self.last_activity)
+        else {
+            return Ok(IdleCheck::Active);
+        };
+        if !self.jobs.is_empty() || last_activity.elapsed() < idle_timeout {
+            return Ok(IdleCheck::Active);
+        }
+
+        match self.lock_requested {
+            None => {
+                self.lock_requested = Some(Instant::now());
+                Ok(IdleCheck::RequestLock)
+            }
+            Some(requested) if requested.elapsed() < LOCK_GRACE => Ok(IdleCheck::Active),
+            Some(_) => {
+                self.lock()?;
+                Ok(IdleCheck::Locked)
+            }
+        }
     }
 
-    pub fn set_key(&mut self, key: [u8; 32]) {
-        self.key = Some(key);
+    /// Locks the app if the lock was requested and it wasn't used again since then.
+    /// Returns true if the app was locked.
+    pub fn lock_if_requested(&mut self) -> Result<bool, String> {
+        if self.lock_requested.is_none() || !self.jobs.is_empty() {
+            self.lock_requested = None;
+            return Ok(false);
+        }
+
+        self.lock()?;
+        Ok(true)
+    }
+
+    /// Locks the app, the key is dropped and the items are closed.
+    /// Only what is needed to check the password and reopen the items is kept.
+    fn lock(&mut self) -> Result<(), String> {
+        let key = self.key.as_ref().ok_or("Log in first")?;
+        let (master_salt, master_params) = key.master_derivation();
+        let session = LockedSession {
+            tag: key.session_tag()?,
+            master_salt,
+            master_params,
+            paths: self
+                .opened_items
+                .iter()
+                .map(|item| item.path.clone())
+                .collect(),
+        };
+        self.reset();
+        self.locked = Some(session);
+
+        Ok(())
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.is_some()
+    }
+
+    /// Tag, master salt and parameters of the locked session, None if the app is not locked
+    pub fn locked_master(&self) -> Option<([u8; 32], [u8; SALT_LEN], KdfParams)> {
+        self.locked
+            .as_ref()
+            .map(|session| (session.tag, session.master_salt, session.master_params))
+    }
+
+    /// Unlocks the app with a key checked against the locked session,
+    /// returns the paths of the items to reopen
+    pub fn unlock(&mut self, key: Keyring) -> Vec<String> {
+        let paths = self
+            .locked
+            .take()
+            .map(|session| session.paths)
+            .unwrap_or_default();
+        self.set_key(key);
+        paths
     }
 
     // pub fn clear_key(&mut self) {
@@ -49,8 +201,8 @@ impl AppState {
     //     self.key
     // }
 
-    pub fn get_encryption_key(&self) -> Result<[u8; 32], &'static str> {
-        self.key.ok_or("Log in first")
+    pub fn get_encryption_key(&self) -> Result<Arc<Keyring>, &'static str> {
+        self.key.clone().ok_or("Log in first")
     }
 
     pub fn is_logged_in(&self) -> bool {
@@ -90,9 +242,47 @@ impl AppState {
                     self.remove_from_id_mapping(&child.id);
                 }
             }
+
+            // Stop watching the folder
+            self.watchers.remove(item_id);
         }
     }
 
+    /// Keeps the watcher of an opened folder, it stops when the folder is closed
+    pub fn add_watcher(&mut self, item_id: String, watcher: RecommendedWatcher) {
+        self.watchers.insert(item_id, watcher);
+    }
+
+    /// Registers a background job, returns its id and the flag set when it is cancelled.
+    /// A job that would start after the app locked or logged out fails.
+    pub fn add_job(&mut self) -> Result<(String, Arc<AtomicBool>), &'static str> {
+        if self.key.is_none() {
+            return Err("The session ended before the job started");
+        }
+
+        let job_id = format!("job_{}", self.next_id);
+        self.next_id += 1;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.jobs.insert(job_id.clone(), cancelled.clone());
+        Ok((job_id, cancelled))
+    }
+
+    /// Asks a job to stop, returns false if it isn't running
+    pub fn cancel_job(&self, job_id: &str) -> bool {
+        match self.jobs.get(job_id) {
+            Some(cancelled) => {
+                cancelled.store(true, AtomicOrdering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_job(&mut self, job_id: &str) {
+        self.jobs.remove(job_id);
+    }
+
     pub fn get_opened_items(&self) -> Vec<FileSystemItemFrontend> {
         self.opened_items
             .iter()
@@ -123,6 +313,10 @@ impl AppState {
     pub fn update_note_path(&mut self, id: &str, parent_id: &str, new_path: String, name: String) {
         // Update path in id_to_path_map
         if let Some(existing_path) = self.id_to_path_map.get_mut(id) {
+            // The file is the same, keep what it looked like
+            if let Some(stamp) = self.file_stamps.remove(existing_path) {
+                self.file_stamps.insert(new_path.clone(), stamp);
+            }
             *existing_path = new_path.clone();
         }
 
@@ -133,6 +327,160 @@ impl AppState {
         }
     }
 
+    /// Remembers what a note looked like when it was read or written
+    pub fn set_file_stamp(&mut self, path: String, stamp: FileStamp) {
+        self.file_stamps.insert(path, stamp);
+    }
+
+    pub fn get_file_stamp(&self, path: &str) -> Option<&FileStamp> {
+        self.file_stamps.get(path)
+    }
+
+    /// Finds the opened item that is or contains the item with the given id
+    pub fn find_root_of(&self, item_id: &str) -> Option<&FileSystemItem> {
+        fn contains(items: &[FileSystemItem], id: &str) -> bool {
+            items.iter().any(|item| {
+                item.id == id || item.children.as_deref().is_some_and(|c| contains(c, id))
+            })
+        }
+
+        self.opened_items.iter().find(|root| {
+            root.id == item_id
+                || root
+                    .children
+                    .as_deref()
+                    .is_some_and(|c| contains(c, item_id))
+        })
+    }
+
+    /// Finds an item inside an opened folder by its path
+    pub fn find_child_by_path(&self, root_id: &str, path: &str) -> Option<&FileSystemItem> {
+        fn find<'a>(items: &'a [FileSystemItem], path: &str) -> Option<&'a FileSystemItem> {
+            items.iter().find_map(|item| {
+                if item.path == path {
+                    return Some(item);
+                }
+                find(item.children.as_deref()?, path)
+            })
+        }
+
+        let root = self.opened_items.iter().find(|item| item.id == root_id)?;
+        find(root.children.as_deref()?, path)
+    }
+
+    /// Inserts an item in the directory with the given path inside an opened folder,
+    /// keeping the order of the scan. Returns the id of the directory.
+    pub fn insert_child(
+        &mut self,
+        root_id: &str,
+        parent_path: &str,
+        item: FileSystemItem,
+    ) -> Option<String> {
+        fn find_dir<'a>(
+            item: &'a mut FileSystemItem,
+            path: &str,
+        ) -> Option<&'a mut FileSystemItem> {
+            if item.path == path {
+                return item.is_directory.then_some(item);
+            }
+            item.children
+                .as_mut()?
+                .iter_mut()
+                .find_map(|child| find_dir(child, path))
+        }
+
+        let root = self
+            .opened_items
+            .iter_mut()
+            .find(|item| item.id == root_id)?;
+        let parent = find_dir(root, parent_path)?;
+        let children = parent.children.get_or_insert_with(Vec::new);
+
+        let index = children
+            .iter()
+            .position(|child| compare_items(&item, child) == Ordering::Less)
+            .unwrap_or(children.len());
+        children.insert(index, item);
+
+        Some(parent.id.clone())
+    }
+
+    /// Removes an item and its children from an opened folder by its path
+    pub fn remove_child(&mut self, root_id: &str, path: &str) -> Option<FileSystemItem> {
+        let removed = self.take_child(root_id, path)?;
+
+        // Forget the ids of the removed items
+        let mut pending = vec![&removed];
+        while let Some(item) = pending.pop() {
+            self.id_to_path_map.remove(&item.id);
+            pending.extend(item.children.iter().flatten());
+        }
+
+        Some(removed)
+    }
+
+    /// Takes an item out of the tree of an opened folder, keeping its ids
+    fn take_child(&mut self, root_id: &str, path: &str) -> Option<FileSystemItem> {
+        fn take(items: &mut Vec<FileSystemItem>, path: &str) -> Option<FileSystemItem> {
+            if let Some(index) = items.iter().position(|item| item.path == path) {
+                return Some(items.remove(index));
+            }
+            items
+                .iter_mut()
+                .find_map(|item| take(item.children.as_mut()?, path))
+        }
+
+        let root = self
+            .opened_items
+            .iter_mut()
+            .find(|item| item.id == root_id)?;
+        take(root.children.as_mut()?, path)
+    }
+
+    /// Moves an item of an opened folder to a new path inside the same folder,
+    /// updating the paths of its children. The ids don't change.
+    /// The name is the one shown in the sidebar, it can differ from the name on disk.
+    /// Returns the id of the directory that now contains it.
+    pub fn move_child(
+        &mut self,
+        root_id: &str,
+        path: &str,
+        new_path: &str,
+        name: String,
+    ) -> Option<String> {
+        let (new_parent_path, _) = new_path.rsplit_once(std::path::MAIN_SEPARATOR)?;
+
+        // Check the destination before taking the item out of the tree
+        let root = self.opened_items.iter().find(|item| item.id == root_id)?;
+        let parent_exists = root.path == new_parent_path
+            || self
+                .find_child_by_path(root_id, new_parent_path)
+                .is_some_and(|parent| parent.is_directory);
+        if !parent_exists {
+            return None;
+        }
+
+        let mut item = self.take_child(root_id, path)?;
+        item.name = name;
+
+        let mut pending = vec![&mut item];
+        while let Some(item) = pending.pop() {
+            let item_path = format!("{}{}", new_path, &item.path[path.len()..]);
+
+            // The files are the same, keep what they looked like
+            if let Some(stamp) = self.file_stamps.remove(&item.path) {
+                self.file_stamps.insert(item_path.clone(), stamp);
+            }
+            self.id_to_path_map
+                .insert(item.id.clone(), item_path.clone());
+            item.path = item_path;
+
+            pending.extend(item.children.iter_mut().flatten());
+        }
+
+        self.insert_child(root_id, new_parent_path, item)
+    }
+
     pub fn find_item_mut<'a>(
         items: &'a mut [FileSystemItem],
         id: &str,
@@ -172,3 +520,12 @@ impl AppState {
         }
     }
 }
+
+/// Order of the items in the sidebar: directories first, then files, both sorted alphabetically
+pub fn compare_items(a: &FileSystemItem, b: &FileSystemItem) -> Ordering {
+    match (a.is_directory, b.is_directory) {
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        _ => a.name.cmp(&b.name),
+    }
+}
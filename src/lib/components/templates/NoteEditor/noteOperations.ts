@@ -1,22 +1,93 @@
-import { throwCustomError } from "$lib/error";
+import { errorMessage, isCommandError, throwCustomError } from "$lib/error";
+import { editorConfig } from "$lib/stores/configs/editorConfig";
+import { currentNote } from "$lib/stores/currentNote";
 import { invoke } from "@tauri-apps/api/core";
+import { ask } from "@tauri-apps/plugin-dialog";
+import { get } from "svelte/store";
+
+// Old versions kept in the history of the vault
+function historyRetention() {
+    const config = get(editorConfig);
+    return {
+        maxVersions: config.historyVersions,
+        maxDays: config.historyDays,
+    };
+}
 
 export async function saveNoteEvent(noteId: string, noteContent: string) {
     try {
         await invoke("save_note", {
             id: noteId,
             content: noteContent,
+            history: historyRetention(),
         });
         return true;
     } catch (error) {
+        if (isCommandError(error) && error.kind === "conflict") {
+            return await resolveConflict(noteId, noteContent);
+        }
+
         throwCustomError(
-            "Failed to save note: " + error,
+            "Failed to save note: " + errorMessage(error),
             "An error occurred while trying to save the note."
         );
         return false;
     }
 }
 
+// The note was changed outside of the app, let the user choose which version to keep.
+// Returns true if the note was saved over the other changes.
+async function resolveConflict(noteId: string, noteContent: string) {
+    try {
+        const overwrite = await ask(
+            "This note was changed outside of the app since it was opened. Do you want to overwrite those changes?",
+            {
+                title: "Note Changed",
+                kind: "warning",
+                okLabel: "Overwrite",
+                cancelLabel: "Keep them",
+            }
+        );
+
+        if (overwrite) {
+            await invoke("overwrite_note", {
+                id: noteId,
+                content: noteContent,
+                history: historyRetention(),
+            });
+            return true;
+        }
+
+        const saveCopy = await ask(
+            "Do you want to save your version as a copy, or reload the note and lose your changes?",
+            {
+                title: "Note Changed",
+                kind: "warning",
+                okLabel: "Save a copy",
+                cancelLabel: "Reload",
+            }
+        );
+
+        if (saveCopy) {
+            await invoke("save_conflict_copy", {
+                id: noteId,
+                content: noteContent,
+            });
+        } else {
+            await invoke("reload_note", {
+                id: noteId,
+                parentId: get(currentNote)?.parentId ?? noteId,
+            });
+        }
+    } catch (error) {
+        throwCustomError(
+            "Failed to resolve save conflict: " + errorMessage(error),
+            "An error occurred while trying to save the note."
+        );
+    }
+    return false;
+}
+
 export async function saveNoteAsEvent(
     noteId: string | undefined,
     title: string,
@@ -57,23 +128,40 @@ export async function saveNoteCopyEvent(
     }
 }
 
+// Returns the title the note was renamed to, or null if it wasn't renamed
 export async function renameNoteEvent(
     noteId: string,
     parentId: string,
     newTitle: string
-) {
+): Promise<string | null> {
     try {
         await invoke("rename_note", {
             id: noteId,
             parentId: parentId,
             newTitle: newTitle,
         });
-        return true;
+        return newTitle;
     } catch (error) {
+        if (isCommandError(error) && error.kind === "exists") {
+            const suggestion = error.suggestion;
+            const useSuggestion =
+                suggestion &&
+                (await ask(
+                    `${error.message}. Do you want to rename it to '${suggestion}'?`,
+                    {
+                        title: "Name Taken",
+                        kind: "warning",
+                    }
+                ));
+            if (!useSuggestion) return null;
+
+            return await renameNoteEvent(noteId, parentId, suggestion);
+        }
+
         throwCustomError(
-            "Failed to rename note: " + error,
-            "An error occurred while trying to rename the note."
+            "Failed to rename note: " + errorMessage(error),
+            errorMessage(error)
         );
-        return false;
+        return null;
     }
 }
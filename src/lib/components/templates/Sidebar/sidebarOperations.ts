@@ -0,0 +1,359 @@
+import { errorMessage, isCommandError, throwCustomError } from "$lib/error";
+import { contextMenu } from "$lib/stores/contextMenu";
+import { securityConfig } from "$lib/stores/configs/securityConfig";
+import { addNotification } from "$lib/stores/notifications";
+import { requestPassword } from "$lib/stores/passwordRequest";
+import {
+    NotificationType,
+    type FileSystemItem,
+    type TrashEntry,
+} from "$lib/types";
+import { invoke } from "@tauri-apps/api/core";
+import { ask } from "@tauri-apps/plugin-dialog";
+import { get } from "svelte/store";
+
+// First name that is not used by the children of the folder: "name", "name 2"...
+function uniqueName(
+    baseName: string,
+    folder: FileSystemItem,
+    extension: string = ""
+) {
+    const names = new Set(folder.children?.map((child) => child.name));
+
+    let name = baseName;
+    for (let n = 2; names.has(name + extension); n++) {
+        name = `${baseName} ${n}`;
+    }
+    return name;
+}
+
+export async function createNoteEvent(folder: FileSystemItem) {
+    try {
+        await invoke("create_note", {
+            parentId: folder.id,
+            title: uniqueName("Untitled Note", folder, ".lockd"),
+        });
+        return true;
+    } catch (error) {
+        throwCustomError(
+            "Failed to create note: " + error,
+            "An error occurred while trying to create the note."
+        );
+        return false;
+    }
+}
+
+export async function createFolderEvent(folder: FileSystemItem) {
+    try {
+        await invoke("create_folder", {
+            parentId: folder.id,
+            name: uniqueName("New Folder", folder),
+        });
+        return true;
+    } catch (error) {
+        throwCustomError(
+            "Failed to create folder: " + error,
+            "An error occurred while trying to create the folder."
+        );
+        return false;
+    }
+}
+
+// Type of the data of the items dragged inside the sidebar
+const ITEM_DRAG_TYPE = "application/x-privatenotes-item";
+
+export function startItemDrag(event: DragEvent, item: FileSystemItem) {
+    event.stopPropagation();
+    event.dataTransfer?.setData(ITEM_DRAG_TYPE, item.id);
+    if (event.dataTransfer) event.dataTransfer.effectAllowed = "move";
+}
+
+// Only items of the sidebar can be dropped on the folders
+export function allowItemDrop(event: DragEvent) {
+    if (event.dataTransfer?.types.includes(ITEM_DRAG_TYPE)) {
+        event.preventDefault();
+        event.dataTransfer.dropEffect = "move";
+    }
+}
+
+export async function dropItemEvent(event: DragEvent, folder: FileSystemItem) {
+    const id = event.dataTransfer?.getData(ITEM_DRAG_TYPE);
+    if (!id) return false;
+
+    event.preventDefault();
+    event.stopPropagation();
+
+    try {
+        // On the back end this will emit an "item-moved" event
+        await invoke("move_item", { id, targetId: folder.id });
+        return true;
+    } catch (error) {
+        throwCustomError(
+            "Failed to move item: " + error,
+            "An error occurred while trying to move the item."
+        );
+        return false;
+    }
+}
+
+export async function renameFolderEvent(
+    folder: FileSystemItem,
+    newName: string
+): Promise<boolean> {
+    try {
+        // On the back end this will emit an "item-moved" event
+        await invoke("rename_folder", { id: folder.id, newName });
+        return true;
+    } catch (error) {
+        if (isCommandError(error) && error.kind === "exists") {
+            const suggestion = error.suggestion;
+            const useSuggestion =
+                suggestion &&
+                (await ask(
+                    `${error.message}. Do you want to rename it to '${suggestion}'?`,
+                    {
+                        title: "Name Taken",
+                        kind: "warning",
+                    }
+                ));
+            if (!useSuggestion) return false;
+
+            return await renameFolderEvent(folder, suggestion);
+        }
+
+        throwCustomError(
+            "Failed to rename folder: " + errorMessage(error),
+            errorMessage(error)
+        );
+        return false;
+    }
+}
+
+export async function deleteItemEvent(item: FileSystemItem) {
+    const answer = await ask(
+        `Do you want to move '${item.name}' to the trash of the folder?`,
+        {
+            title: "Delete",
+            kind: "warning",
+        }
+    );
+    if (!answer) return false;
+
+    try {
+        // On the back end this will emit an "item-removed" event
+        await invoke("delete_item", { id: item.id });
+        return true;
+    } catch (error) {
+        throwCustomError(
+            "Failed to delete item: " + error,
+            "An error occurred while trying to delete the item."
+        );
+        return false;
+    }
+}
+
+// Moves the most recently deleted item of an opened folder back to its place
+export async function restoreLastDeletedEvent(folder: FileSystemItem) {
+    try {
+        const entries: TrashEntry[] = await invoke("list_trash", {
+            folderId: folder.id,
+        });
+        if (entries.length === 0) {
+            addNotification("The trash is empty", NotificationType.INFO);
+            return false;
+        }
+
+        await invoke("restore_trash_item", {
+            folderId: folder.id,
+            entryId: entries[0].id,
+        });
+        addNotification(
+            `Restored '${entries[0].displayPath || entries[0].originalPath}'`,
+            NotificationType.SUCCESS
+        );
+        return true;
+    } catch (error) {
+        throwCustomError(
+            "Failed to restore item: " + error,
+            "An error occurred while trying to restore the item."
+        );
+        return false;
+    }
+}
+
+export async function emptyTrashEvent(folder: FileSystemItem) {
+    const answer = await ask(
+        "Do you want to permanently delete the items in the trash of this folder?",
+        {
+            title: "Empty Trash",
+            kind: "warning",
+        }
+    );
+    if (!answer) return false;
+
+    try {
+        await invoke("empty_trash", { folderId: folder.id });
+        return true;
+    } catch (error) {
+        throwCustomError(
+            "Failed to empty trash: " + error,
+            "An error occurred while trying to empty the trash."
+        );
+        return false;
+    }
+}
+
+// Context menu of the folders in the sidebar
+export function showFolderContextMenu(
+    event: MouseEvent,
+    folder: FileSystemItem,
+    startRename: () => void
+) {
+    event.preventDefault();
+    event.stopPropagation();
+
+    contextMenu.show(event.clientX, event.clientY, [
+        { text: "New Note", action: () => createNoteEvent(folder) },
+        { text: "New Folder", action: () => createFolderEvent(folder) },
+        { text: "Rename", action: startRename },
+        { text: "Delete", action: () => deleteItemEvent(folder) },
+    ]);
+}
+
+// Encrypts or decrypts the names of the notes and folders of an opened folder on disk
+export async function setNameEncryptionEvent(
+    folder: FileSystemItem,
+    encryptNames: boolean
+) {
+    const answer = await ask(
+        `Every note and folder in '${folder.name}' will be renamed on disk and the folder will be opened again. Unsaved changes will be lost. Do you want to continue?`,
+        {
+            title: encryptNames ? "Encrypt Names" : "Decrypt Names",
+            kind: "warning",
+        }
+    );
+    if (!answer) return false;
+
+    try {
+        await invoke("set_name_encryption", {
+            folderId: folder.id,
+            encryptNames,
+        });
+        return true;
+    } catch (error) {
+        throwCustomError(
+            "Failed to change name encryption: " + error,
+            "An error occurred while trying to rename the items of the folder."
+        );
+        return false;
+    }
+}
+
+// Ciphers of the new files of a vault, the existing files keep theirs
+const cipherNames: Record<string, string> = {
+    "aes-256-gcm": "AES-256-GCM",
+    "xchacha20-poly1305": "XChaCha20-Poly1305",
+};
+
+// Changes the cipher used for the new files of an opened vault
+export async function setVaultCipherEvent(
+    folder: FileSystemItem,
+    cipher: string
+) {
+    try {
+        await invoke("set_vault_cipher", { folderId: folder.id, cipher });
+        addNotification(
+            `New files in '${folder.name}' will use ${cipherNames[cipher]}`,
+            NotificationType.SUCCESS
+        );
+    } catch (error) {
+        throwCustomError(
+            "Failed to change cipher: " + error,
+            "An error occurred while trying to change the cipher of the folder."
+        );
+    }
+}
+
+// Asks for the new username and password of an opened vault and rewraps its key.
+// The files still encrypted with the old password are re-encrypted first, the modal shows the progress.
+export function changePasswordEvent(folder: FileSystemItem) {
+    requestPassword({
+        title: "Change password",
+        message: `New username and password of '${folder.name}'. Files encrypted with the old password are re-encrypted first, it can take a while.`,
+        confirm: true,
+        progressEvent: "password-change-progress",
+        submit: async (username, password) => {
+            const config = get(securityConfig);
+            await invoke("change_password", {
+                folderId: folder.id,
+                username,
+                password,
+                kdfParams: {
+                    memory: config.kdfMemory * 1024,
+                    iterations: config.kdfIterations,
+                    parallelism: config.kdfParallelism,
+                },
+            });
+            addNotification(
+                `Password of '${folder.name}' changed`,
+                NotificationType.SUCCESS
+            );
+        },
+    });
+}
+
+// Context menu of the opened folders, they can't be deleted but have a trash
+export async function showRootContextMenu(
+    event: MouseEvent,
+    folder: FileSystemItem
+) {
+    event.preventDefault();
+    event.stopPropagation();
+
+    let encryptedNames = false;
+    try {
+        encryptedNames = await invoke("get_name_encryption", {
+            folderId: folder.id,
+        });
+    } catch (error) {
+        console.error("Failed to get name encryption: ", error);
+    }
+
+    let cipher = "aes-256-gcm";
+    try {
+        cipher = await invoke("get_vault_cipher", { folderId: folder.id });
+    } catch (error) {
+        console.error("Failed to get vault cipher: ", error);
+    }
+    const otherCipher =
+        cipher === "aes-256-gcm" ? "xchacha20-poly1305" : "aes-256-gcm";
+
+    contextMenu.show(event.clientX, event.clientY, [
+        { text: "New Note", action: () => createNoteEvent(folder) },
+        { text: "New Folder", action: () => createFolderEvent(folder) },
+        {
+            text: "Restore Last Deleted",
+            action: () => restoreLastDeletedEvent(folder),
+        },
+        { text: "Empty Trash", action: () => emptyTrashEvent(folder) },
+        {
+            text: encryptedNames ? "Decrypt Names" : "Encrypt Names",
+            action: () => setNameEncryptionEvent(folder, !encryptedNames),
+        },
+        {
+            text: `Use ${cipherNames[otherCipher]}`,
+            action: () => setVaultCipherEvent(folder, otherCipher),
+        },
+        { text: "Change Password", action: () => changePasswordEvent(folder) },
+    ]);
+}
+
+// Context menu of the files in the sidebar
+export function showFileContextMenu(event: MouseEvent, file: FileSystemItem) {
+    event.preventDefault();
+    event.stopPropagation();
+
+    contextMenu.show(event.clientX, event.clientY, [
+        { text: "Delete", action: () => deleteItemEvent(file) },
+    ]);
+}
@@ -1,10 +1,58 @@
+import { invoke } from "@tauri-apps/api/core";
 import { addNotification } from "./stores/notifications";
+import { requestPassword } from "./stores/passwordRequest";
 import { NotificationType } from "./types";
 
+// Error returned by the commands that report a kind of error
+export interface CommandError {
+    kind: "conflict" | "exists" | "tampered" | "other";
+    message: string;
+    id?: string;
+    // Name that is taken and a free one, for the "exists" kind
+    name?: string;
+    suggestion?: string;
+}
+
+export function isCommandError(error: unknown): error is CommandError {
+    return typeof error === "object" && error !== null && "kind" in error;
+}
+
+// Message of an error from a command, which can be a string or a CommandError
+export function errorMessage(error: unknown): string {
+    return isCommandError(error) ? error.message : String(error);
+}
+
+// Part of the error of the files whose key wasn't derived at login,
+// see PASSWORD_NEEDED_ERROR in keyring.rs
+const passwordNeeded = "enter your password again to open it";
+
 export async function throwCustomError(
     err: string,
     userMessage: string = err
 ): Promise<void> {
     console.error("Error: ", err);
+
+    if (err.includes(passwordNeeded)) {
+        askMissingKeys();
+        return;
+    }
     addNotification(userMessage, NotificationType.ERROR);
 }
+
+// Ask for the password to derive the keys of the files written with another key
+function askMissingKeys() {
+    requestPassword({
+        title: "Password needed",
+        message:
+            "This file was encrypted with another key of your account. " +
+            "Enter your username and password, then open it again.",
+        confirm: false,
+        submit: async (username, password) => {
+            await invoke("derive_missing_keys", { username, password });
+            addNotification(
+                "Keys derived, open the file again",
+                NotificationType.SUCCESS
+            );
+        },
+    });
+}
@@ -4,7 +4,10 @@ import {
     type ConfigurationGroup,
     type Options,
 } from "$lib/types";
+import { invoke } from "@tauri-apps/api/core";
+import { throwCustomError } from "$lib/error";
 import { configGroupList } from "./configGroups";
+import { securityConfig } from "./configs/securityConfig";
 
 function makeCommandsFromGroup(group: ConfigurationGroup, store: Options) {
     const commands: Command[] = [];
@@ -62,10 +65,47 @@ function makeCommandsFromGroup(group: ConfigurationGroup, store: Options) {
     return commands;
 }
 
+interface KdfParams {
+    memory: number;
+    iterations: number;
+    parallelism: number;
+}
+
+// Benchmark Argon2 and store the parameters that take about the given time
+async function calibrateKdf(targetMs?: number) {
+    try {
+        const params: KdfParams = await invoke("calibrate_kdf", { targetMs });
+        securityConfig.update((cfg: Options) => {
+            cfg.kdfMemory = Math.round(params.memory / 1024);
+            cfg.kdfIterations = params.iterations;
+            cfg.kdfParallelism = params.parallelism;
+            return cfg;
+        });
+    } catch (error) {
+        throwCustomError(
+            "Failed to calibrate key derivation: " + error,
+            "An error occurred while calibrating the key derivation."
+        );
+    }
+}
+
+const securityCommands: Command[] = [
+    {
+        name: "calibrate kdf",
+        pattern: /^calibrate\s+kdf(?:\s+(\d+))?$/i,
+        execute: (args: string[]) => {
+            void calibrateKdf(args[0] ? Number(args[0]) : undefined);
+        },
+    },
+];
+
 // Generate commands for all config groups
-export const commandList = configGroupList.flatMap((group) =>
-    makeCommandsFromGroup(group, group.store)
-);
+export const commandList = [
+    ...configGroupList.flatMap((group) =>
+        makeCommandsFromGroup(group, group.store)
+    ),
+    ...securityCommands,
+];
 
 export function runCommandScript(script: string): void {
     const normalizedScript = script.replace(/\r\n|\r/g, "\n");
@@ -3,11 +3,13 @@ import type { ConfigurationGroup } from "$lib/types";
 import { invoke } from "@tauri-apps/api/core";
 import { appearanceConfigGroup } from "./configs/appearanceConfig";
 import { editorConfigGroup } from "./configs/editorConfig";
+import { securityConfigGroup } from "./configs/securityConfig";
 
 // Unified config group list
 export const configGroupList: ConfigurationGroup[] = [
     appearanceConfigGroup,
     editorConfigGroup,
+    securityConfigGroup,
 ];
 
 function debounce<T extends (...args: any[]) => void>(fn: T, delay: number) {
@@ -66,6 +66,26 @@ const editorConfigSections: ConfigurationSection[] = [
             },
         ],
     },
+    {
+        name: "History",
+        options: [
+            {
+                key: "historyVersions",
+                name: "Versions Kept (0 = no limit)",
+                defaultValue: 20,
+                min: 0,
+                type: OptionType.NUMBER,
+            },
+            {
+                key: "historyDays",
+                name: "Days Kept (0 = no limit)",
+                defaultValue: 30,
+                min: 0,
+                max: 36500,
+                type: OptionType.NUMBER,
+            },
+        ],
+    },
 ];
 
 const defaultEditorConfig = optionsFromSections(editorConfigSections);
@@ -0,0 +1,98 @@
+import {
+    OptionType,
+    type ConfigurationGroup,
+    type ConfigurationSection,
+    type Options,
+} from "$lib/types";
+import { writable } from "svelte/store";
+import { getInitialConfig, optionsFromSections } from "./configUtils";
+
+const securityConfigSections: ConfigurationSection[] = [
+    {
+        name: "Key Derivation",
+        options: [
+            {
+                key: "kdfMemory",
+                name: "Memory (MiB)",
+                defaultValue: 19,
+                min: 8,
+                max: 4096,
+                type: OptionType.NUMBER,
+            },
+            {
+                key: "kdfIterations",
+                name: "Iterations",
+                defaultValue: 2,
+                min: 1,
+                max: 64,
+                type: OptionType.NUMBER,
+            },
+            {
+                key: "kdfParallelism",
+                name: "Parallelism",
+                defaultValue: 1,
+                min: 1,
+                max: 16,
+                type: OptionType.NUMBER,
+            },
+        ],
+    },
+    {
+        name: "Encryption",
+        options: [
+            {
+                key: "padNotes",
+                name: "Hide Note Lengths",
+                defaultValue: false,
+                type: OptionType.BOOLEAN,
+            },
+            {
+                key: "compress",
+                name: "Compress Notes and Files",
+                defaultValue: false,
+                type: OptionType.BOOLEAN,
+            },
+        ],
+    },
+    {
+        name: "Session",
+        options: [
+            {
+                key: "autoLockMinutes",
+                name: "Auto-Lock After Idle Minutes (0 = Never)",
+                defaultValue: 0,
+                min: 0,
+                max: 1440,
+                type: OptionType.NUMBER,
+            },
+        ],
+    },
+];
+
+const defaultSecurityConfig = optionsFromSections(securityConfigSections);
+
+const securityKey = "security";
+
+export const securityConfig = writable<Options>({
+    ...defaultSecurityConfig,
+});
+
+void getInitialConfig().then((config) => {
+    securityConfig.update((currentConfig) => ({
+        ...currentConfig,
+        ...config[securityKey],
+    }));
+});
+
+function setSecurityConfig(newConfig: Options) {
+    securityConfig.set(newConfig);
+}
+
+export const securityConfigGroup: ConfigurationGroup = {
+    name: "Security",
+    key: securityKey,
+    sections: securityConfigSections,
+    defaults: defaultSecurityConfig,
+    store: securityConfig,
+    setter: setSecurityConfig,
+};
@@ -0,0 +1,15 @@
+// Work that has to finish before the app locks itself, like saving the open note
+const beforeLockHooks = new Set<() => Promise<void>>();
+
+// Returns the function that removes the hook
+export function onBeforeLock(hook: () => Promise<void>) {
+    beforeLockHooks.add(hook);
+    return () => {
+        beforeLockHooks.delete(hook);
+    };
+}
+
+// A hook that fails doesn't stop the others, the app locks anyway
+export async function runBeforeLock() {
+    await Promise.allSettled([...beforeLockHooks].map((hook) => hook()));
+}
@@ -0,0 +1,18 @@
+import { writable } from "svelte/store";
+
+// Request of the username and password, shown by the password modal of the layout
+export interface PasswordRequest {
+    title: string;
+    message: string;
+    // Ask for the password twice, for new passwords
+    confirm: boolean;
+    // Event with the progress of the submit ({ done, total, path }), if it reports one
+    progressEvent?: string;
+    submit: (username: string, password: string) => Promise<void>;
+}
+
+export const passwordRequest = writable<PasswordRequest | null>(null);
+
+export function requestPassword(request: PasswordRequest) {
+    passwordRequest.set(request);
+}
@@ -10,6 +10,55 @@ export interface FileSystemItem {
     collapsed: boolean;
 }
 
+// An item appeared inside an opened folder
+export interface ItemAddedEvent {
+    rootId: string;
+    parentItemId: string;
+    item: FileSystemItem;
+}
+
+// An item inside an opened folder was removed or changed
+export interface ItemEvent {
+    rootId: string;
+    id: string;
+}
+
+// Item deleted from an opened folder, kept in the trash of the folder
+export interface TrashEntry {
+    id: string;
+    name: string;
+    originalPath: string;
+    // Path with the names decrypted, empty in old entries
+    displayPath: string;
+    isDirectory: boolean;
+    deletedAt: number;
+}
+
+// Background job that encrypts or decrypts the files of a folder
+export interface FolderJob {
+    jobId: string;
+    kind: "encrypt" | "decrypt" | "archive" | "extract";
+    path: string;
+    done: number;
+    total: number;
+    // Bytes of the file being processed, for jobs of a single file
+    bytes?: FileProgress;
+}
+
+// Progress of a file that is being encrypted or decrypted, in bytes
+export interface FileProgress {
+    path: string;
+    done: number;
+    total: number;
+}
+
+// Report of a folder job, the files that failed didn't stop it
+export interface FolderJobReport extends FolderJob {
+    output: string;
+    cancelled: boolean;
+    failed: { path: string; error: string }[];
+}
+
 export interface CurrentNote {
     id: string;
     parentId: string;